@@ -0,0 +1,130 @@
+use tonic::{Request, Response, Status};
+use tonic::service::Interceptor;
+
+use crate::raft_rpc::admin_server::Admin;
+
+use crate::raft_rpc::{StatusRequest, StatusResponse};
+use crate::raft_rpc::{TakeSnapshotRequest, TakeSnapshotResponse};
+use crate::raft_rpc::{AddServerRequest, RemoveServerRequest, MembershipResponse};
+use crate::raft_rpc::{TransferLeadershipRequest, TransferLeadershipResponse};
+
+use crate::{RaftMessage, ClientData};
+
+use tokio::sync::{mpsc, oneshot};
+
+use subtle::ConstantTimeEq;
+
+use tracing::info;
+
+#[derive(Debug)]
+pub struct AdminService<T: ClientData> {
+    tx_rpc: mpsc::UnboundedSender<RaftMessage<T>>
+}
+
+impl<T: ClientData> AdminService<T> {
+    pub fn new(tx_rpc: mpsc::UnboundedSender<RaftMessage<T>>) -> AdminService<T> {
+        AdminService { tx_rpc }
+    }
+
+    async fn ask(&self, msg: impl FnOnce(oneshot::Sender<RaftMessage<T>>) -> RaftMessage<T>)
+        -> Result<RaftMessage<T>, Status> {
+        let (tx, rx) = oneshot::channel();
+        if let Err(err) = self.tx_rpc.send(msg(tx)) {
+            return Err(Status::internal(err.to_string()));
+        }
+        rx.await.map_err(|err| Status::internal(err.to_string()))
+    }
+
+    async fn membership(&self, msg: impl FnOnce(oneshot::Sender<RaftMessage<T>>) -> RaftMessage<T>)
+        -> Result<Response<MembershipResponse>, Status> {
+        match self.ask(msg).await? {
+            RaftMessage::MembershipResp{payload, status} => {
+                if let Some(status) = status {
+                    return Err(status);
+                }
+                payload.map(Response::new).ok_or_else(|| Status::internal("Empty membership response"))
+            },
+            RaftMessage::ClientError{body} => Err(Status::unavailable(body)),
+            _ => Err(Status::unknown("Unkown response recived"))
+        }
+    }
+}
+
+// Every admin request has to carry `authorization: Bearer <token>`.
+#[derive(Clone)]
+pub struct AdminAuth {
+    token: String
+}
+
+impl AdminAuth {
+    pub fn new(token: String) -> AdminAuth {
+        AdminAuth { token }
+    }
+}
+
+impl Interceptor for AdminAuth {
+    fn call(&mut self, request: Request<()>) -> Result<Request<()>, Status> {
+        let given = request.metadata().get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        match given {
+            Some(given) if bool::from(given.as_bytes().ct_eq(self.token.as_bytes())) => Ok(request),
+            _ => Err(Status::unauthenticated("Invalid admin token"))
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl<T: ClientData> Admin for AdminService<T> {
+    async fn status(&self, _request: Request<StatusRequest>)
+        -> Result<Response<StatusResponse>, Status> {
+        match self.ask(|tx| RaftMessage::StatusMsg { tx }).await? {
+            RaftMessage::StatusResp{payload} => Ok(Response::new(payload)),
+            _ => Err(Status::unknown("Unkown response recived"))
+        }
+    }
+
+    async fn take_snapshot(&self, _request: Request<TakeSnapshotRequest>)
+        -> Result<Response<TakeSnapshotResponse>, Status> {
+        info!("Asked to take a snapshot");
+        match self.ask(|tx| RaftMessage::TakeSnapshotMsg { tx }).await? {
+            RaftMessage::TakeSnapshotResp{payload, status} => {
+                if let Some(status) = status {
+                    return Err(status);
+                }
+                payload.map(Response::new).ok_or_else(|| Status::internal("Empty snapshot response"))
+            },
+            _ => Err(Status::unknown("Unkown response recived"))
+        }
+    }
+
+    async fn transfer_leadership(&self, request: Request<TransferLeadershipRequest>)
+        -> Result<Response<TransferLeadershipResponse>, Status> {
+        let body = request.into_inner();
+        info!("Asked to transfer the leadership to {:?}", &body.node_id);
+        match self.ask(|tx| RaftMessage::TransferLeaderMsg { body, tx }).await? {
+            RaftMessage::TransferLeaderResp{payload, status} => {
+                if let Some(status) = status {
+                    return Err(status);
+                }
+                payload.map(Response::new).ok_or_else(|| Status::internal("Empty transfer response"))
+            },
+            RaftMessage::ClientError{body} => Err(Status::unavailable(body)),
+            _ => Err(Status::unknown("Unkown response recived"))
+        }
+    }
+
+    async fn add_server(&self, request: Request<AddServerRequest>)
+        -> Result<Response<MembershipResponse>, Status> {
+        let body = request.into_inner();
+        info!("{:?} Asked to join", &body.node_id);
+        self.membership(|tx| RaftMessage::AddServerMsg { body, tx }).await
+    }
+
+    async fn remove_server(&self, request: Request<RemoveServerRequest>)
+        -> Result<Response<MembershipResponse>, Status> {
+        let body = request.into_inner();
+        info!("{:?} Asked to leave", &body.node_id);
+        self.membership(|tx| RaftMessage::RemoveServerMsg { body, tx }).await
+    }
+}
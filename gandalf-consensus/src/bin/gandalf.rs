@@ -1,6 +1,8 @@
 use serde::{Serialize, Deserialize};
 use gandalf_consensus::{server, ConfigMap};
-use gandalf_consensus::{DEFAULT_PORT, HEARTBEAT, TIMEOUT};
+use gandalf_consensus::{DEFAULT_PORT, HEARTBEAT, TIMEOUT, FSYNC, CLOCK_DRIFT};
+use gandalf_consensus::{MAX_BATCH_ENTRIES, MAX_BATCH_SIZE, MAX_INFLIGHT, SNAPSHOT_CHUNK_SIZE};
+use gandalf_consensus::{CLIENT_TIMEOUT, SESSION_EXPIRY, SNAPSHOT_PATH};
 
 use tracing_subscriber;
 use tokio::signal;
@@ -8,6 +10,7 @@ use tokio::signal;
 use structopt::StructOpt;
 
 use gandalf_consensus::client::kvs::{KvsParser, KvsTracker}; 
+use gandalf_consensus::storage::FsyncPolicy;
 
 fn read_config(path: &str) -> serde_yaml::Result<Option<Cli>> {
     let config_file = std::fs::File::open(path).ok();
@@ -18,6 +21,41 @@ fn read_config(path: &str) -> serde_yaml::Result<Option<Cli>> {
     Ok(None)
 }
 
+fn default_fsync() -> String {
+    FSYNC.to_string()
+}
+
+fn default_snapshot_path() -> String {
+    SNAPSHOT_PATH.to_string()
+}
+
+fn default_clock_drift() -> u64 {
+    CLOCK_DRIFT.parse().unwrap()
+}
+
+fn default_max_batch_entries() -> u64 {
+    MAX_BATCH_ENTRIES.parse().unwrap()
+}
+
+fn default_max_batch_size() -> u64 {
+    MAX_BATCH_SIZE.parse().unwrap()
+}
+
+fn default_max_inflight() -> u64 {
+    MAX_INFLIGHT.parse().unwrap()
+}
+
+fn default_snapshot_chunk_size() -> u64 {
+    SNAPSHOT_CHUNK_SIZE.parse().unwrap()
+}
+
+fn default_client_timeout() -> u64 {
+    CLIENT_TIMEOUT.parse().unwrap()
+}
+
+fn default_session_expiry() -> u64 {
+    SESSION_EXPIRY.parse().unwrap()
+}
 
 #[tokio::main]
 pub async fn main() -> Result<(), gandalf_consensus::Error> {
@@ -34,12 +72,40 @@ pub async fn main() -> Result<(), gandalf_consensus::Error> {
 
     let nodes = cli.nodes.ok_or("You must pass list of nodes")?;
 
-    let config = ConfigMap::new(cli.host, cli.port, nodes, cli.heartbeat,
+    let mut config = ConfigMap::new(cli.host, cli.port, nodes, cli.heartbeat,
         cli.timeout, cli.connection_host, cli.connection_port, cli.snapshot_offset)?;
+    config.follower_read_index = cli.follower_read_index;
+    config.lease_read = cli.lease_read;
+    config.redirect = cli.redirect;
+    config.clock_drift = cli.clock_drift;
+    config.learner = cli.learner;
+    config.max_batch_entries = cli.max_batch_entries;
+    config.max_batch_size = cli.max_batch_size;
+    config.max_inflight = cli.max_inflight;
+    config.snapshot_chunk_size = cli.snapshot_chunk_size;
+    config.snapshot_path = cli.snapshot_path.clone();
+    config.client_timeout = cli.client_timeout;
+    config.tls_ca = cli.tls_ca;
+    config.tls_cert = cli.tls_cert;
+    config.tls_key = cli.tls_key;
+    config.client_tls_cert = cli.client_tls_cert;
+    config.client_tls_key = cli.client_tls_key;
+    config.metrics_addr = cli.metrics_addr;
+    config.admin_addr = cli.admin_addr;
+    config.admin_token = cli.admin_token;
+    if let Some(users) = cli.users {
+        config.set_users(users)?;
+    }
+    if let Some(learners) = cli.learners {
+        config.set_learners(learners)?;
+    }
 
     let address = format!("{}:{}", cli.client_host, cli.client_port).parse()?;
 
-    let tracker = KvsTracker::new(address, cli.snapshot_path, cli.snapshot_offset);
+    let fsync: FsyncPolicy = cli.fsync.parse()?;
+
+    let mut tracker = KvsTracker::new(address, cli.snapshot_path, fsync)?;
+    tracker.set_session_expiry(cli.session_expiry);
 
     server::run(signal::ctrl_c(), config, KvsParser, tracker).await?;
 
@@ -59,6 +125,14 @@ struct Cli {
     #[structopt(name = "nodes", long = "--node")]
     nodes: Option<Vec<String>>,
 
+    #[structopt(name = "learners", long = "--learner_node")]
+    #[serde(default)]
+    learners: Option<Vec<String>>,
+
+    #[structopt(name = "learner", long = "--learner")]
+    #[serde(default)]
+    learner: bool,
+
     #[structopt(name = "heartbeat", long = "--heart", default_value = HEARTBEAT)]
     heartbeat: u64,
 
@@ -80,9 +154,90 @@ struct Cli {
     #[structopt(name = "connection_host", long = "--connection_host", default_value = "127.0.0.1")]
     connection_host: String,
 
-    #[structopt(name = "snapshot_path", long = "--snap", default_value = "/tmp")]
+    #[structopt(name = "snapshot_path", long = "--snap", default_value = SNAPSHOT_PATH)]
+    #[serde(default = "default_snapshot_path")]
     snapshot_path: String,
 
+    #[structopt(name = "fsync", long = "--fsync", default_value = FSYNC)]
+    #[serde(default = "default_fsync")]
+    fsync: String,
+
+    #[structopt(name = "follower_read_index", long = "--follower_read_index")]
+    #[serde(default)]
+    follower_read_index: bool,
+
+    #[structopt(name = "lease_read", long = "--lease_read")]
+    #[serde(default)]
+    lease_read: bool,
+
+    #[structopt(name = "redirect", long = "--redirect")]
+    #[serde(default)]
+    redirect: bool,
+
+    #[structopt(name = "clock_drift", long = "--clock_drift", default_value = CLOCK_DRIFT)]
+    #[serde(default = "default_clock_drift")]
+    clock_drift: u64,
+
+    #[structopt(name = "max_batch_entries", long = "--max_batch_entries", default_value = MAX_BATCH_ENTRIES)]
+    #[serde(default = "default_max_batch_entries")]
+    max_batch_entries: u64,
+
+    #[structopt(name = "max_batch_size", long = "--max_batch_size", default_value = MAX_BATCH_SIZE)]
+    #[serde(default = "default_max_batch_size")]
+    max_batch_size: u64,
+
+    #[structopt(name = "max_inflight", long = "--max_inflight", default_value = MAX_INFLIGHT)]
+    #[serde(default = "default_max_inflight")]
+    max_inflight: u64,
+
+    #[structopt(name = "snapshot_chunk_size", long = "--snapshot_chunk_size", default_value = SNAPSHOT_CHUNK_SIZE)]
+    #[serde(default = "default_snapshot_chunk_size")]
+    snapshot_chunk_size: u64,
+
+    #[structopt(name = "client_timeout", long = "--client_timeout", default_value = CLIENT_TIMEOUT)]
+    #[serde(default = "default_client_timeout")]
+    client_timeout: u64,
+
+    #[structopt(name = "session_expiry", long = "--session_expiry", default_value = SESSION_EXPIRY)]
+    #[serde(default = "default_session_expiry")]
+    session_expiry: u64,
+
+    #[structopt(name = "tls_ca", long = "--tls_ca")]
+    #[serde(default)]
+    tls_ca: Option<String>,
+
+    #[structopt(name = "tls_cert", long = "--tls_cert")]
+    #[serde(default)]
+    tls_cert: Option<String>,
+
+    #[structopt(name = "tls_key", long = "--tls_key")]
+    #[serde(default)]
+    tls_key: Option<String>,
+
+    #[structopt(name = "client_tls_cert", long = "--client_tls_cert")]
+    #[serde(default)]
+    client_tls_cert: Option<String>,
+
+    #[structopt(name = "client_tls_key", long = "--client_tls_key")]
+    #[serde(default)]
+    client_tls_key: Option<String>,
+
+    #[structopt(name = "users", long = "--user")]
+    #[serde(default)]
+    users: Option<Vec<String>>,
+
+    #[structopt(name = "metrics_addr", long = "--metrics_addr")]
+    #[serde(default)]
+    metrics_addr: Option<String>,
+
+    #[structopt(name = "admin_addr", long = "--admin_addr")]
+    #[serde(default)]
+    admin_addr: Option<String>,
+
+    #[structopt(name = "admin_token", long = "--admin_token")]
+    #[serde(default)]
+    admin_token: Option<String>,
+
     #[structopt(name = "config", long = "--config", default_value = "/etc/gandalf.conf")]
     #[serde(skip)]
     config: String
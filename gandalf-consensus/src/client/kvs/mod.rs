@@ -1,5 +1,8 @@
 pub mod parser;
 pub use parser::KvsParser;
 
+pub mod pool;
+pub use pool::KvsPool;
+
 pub mod tracker;
 pub use tracker::KvsTracker;
@@ -4,7 +4,7 @@ use std::io::Cursor;
 use gandalf_kvs::frame::{self, Frame};
 use gandalf_kvs::Command;
 
-use crate::parser::{Parser, Kind};
+use crate::parser::{Parser, Kind, Session};
 
 use std::io::Write;
 
@@ -60,6 +60,74 @@ impl KvsParser {
         Ok(())
     }
 
+    fn as_string(&self, frame: &Frame) -> crate::Result<String> {
+        match frame {
+            Frame::Simple(val) => Ok(val.clone()),
+            Frame::Bulk(val) => Ok(std::str::from_utf8(val)?.to_string()),
+            frame => Err(format!("protocol error; expected string, got {:?}", frame).into())
+        }
+    }
+
+    // Writes may be prefixed with `SESSION <client_id> <sequence>` so that
+    // retries of the same request are applied only once.
+    fn split_session(&self, frame: Frame) -> crate::Result<(Frame, Option<Session>)> {
+        match frame {
+            Frame::Array(mut parts) if parts.len() > 3
+                && matches!(self.as_string(&parts[0]), Ok(name) if name.eq_ignore_ascii_case("session")) => {
+                let command = parts.split_off(3);
+                let client_id = self.as_string(&parts[1])?;
+                let sequence = self.as_string(&parts[2])?.parse()?;
+                Ok((Frame::Array(command), Some(Session { client_id, sequence })))
+            },
+            frame => Ok((frame, None))
+        }
+    }
+
+    // `CLIENT TIMEOUT <ms>` sets the request deadline of the connection, zero
+    // goes back to the configured one.
+    fn client_timeout(&self, frame: &Frame) -> crate::Result<Option<u64>> {
+        let is = |frame: &Frame, name: &str|
+            matches!(self.as_string(frame), Ok(value) if value.eq_ignore_ascii_case(name));
+        match frame {
+            Frame::Array(parts) if parts.len() == 3 && is(&parts[0], "client") && is(&parts[1], "timeout") =>
+                Ok(Some(self.as_string(&parts[2])?.parse()?)),
+            _ => Ok(None)
+        }
+    }
+
+    // `AUTH <user> <password>`, or `AUTH <password>` for the default user.
+    fn auth(&self, frame: &Frame) -> crate::Result<Option<(String, String)>> {
+        let parts = match frame {
+            Frame::Array(parts) if !parts.is_empty() && matches!(self.as_string(&parts[0]),
+                Ok(name) if name.eq_ignore_ascii_case("auth")) => parts,
+            _ => return Ok(None)
+        };
+        match parts.len() {
+            2 => Ok(Some(("default".to_string(), self.as_string(&parts[1])?))),
+            3 => Ok(Some((self.as_string(&parts[1])?, self.as_string(&parts[2])?))),
+            _ => Err("wrong number of arguments for 'auth' command".into())
+        }
+    }
+
+    fn command(&self, frame: Frame) -> crate::Result<Kind<Frame>> {
+        if let Some(timeout) = self.client_timeout(&frame)? {
+            return Ok(Kind::Timeout(timeout));
+        }
+
+        if let Some((user, password)) = self.auth(&frame)? {
+            return Ok(Kind::Auth(user, password));
+        }
+
+        let (frame, session) = self.split_session(frame)?;
+
+        match Command::from_frame(frame.clone())? {
+            Command::Get(_) => Ok(Kind::Read(frame)),
+            Command::Snap(_) => Ok(Kind::Read(frame)),
+            Command::Set(_) => Ok(Kind::Write(frame, session)),
+            Command::Load(_) => Ok(Kind::Write(frame, session)),
+        }
+    }
+
     fn write_decimal(&self, buf: &mut BytesMut, value: u64) -> crate::Result<()> {
         let mut buff = [0u8; 20];
         let mut buff = Cursor::new(&mut buff[..]);
@@ -87,13 +155,11 @@ impl Parser<Frame> for KvsParser {
                 
                 buffer.advance(len);
 
-                match Command::from_frame(frame.clone())? {
-                    Command::Get(_) => return Ok(Some(Kind::Read(frame))),
-                    Command::Snap(_) => return Ok(Some(Kind::Read(frame))),
-                    Command::Set(_) => return Ok(Some(Kind::Write(frame))),
-                    Command::Load(_) => return Ok(Some(Kind::Write(frame))),
+                // A malformed command is answered, it does not end the connection.
+                match self.command(frame) {
+                    Ok(kind) => Ok(Some(kind)),
+                    Err(err) => Ok(Some(Kind::Invalid(format!("ERR {}", err))))
                 }
-
             }
 
             Err(frame::Error::Incomplete) => Ok(None),
@@ -122,4 +188,12 @@ impl Parser<Frame> for KvsParser {
     fn into_error(&self, data: &str) -> crate::Result<Bytes> {
         self.unparse(Frame::Error(data.to_string()))
     }
+
+    fn into_ok(&self) -> crate::Result<Bytes> {
+        self.unparse(Frame::Simple("OK".to_string()))
+    }
+
+    fn into_redirect(&self, leader: &str) -> crate::Result<Bytes> {
+        self.unparse(Frame::Error(format!("MOVED {}", leader)))
+    }
 }
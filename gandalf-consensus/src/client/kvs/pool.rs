@@ -0,0 +1,90 @@
+use gandalf_kvs::{Frame, Connection};
+
+use tokio::net::TcpStream;
+use tokio::sync::Semaphore;
+use tokio::time::{self, Duration, Instant};
+
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use tracing::info;
+
+pub const POOL_SIZE: usize = 16;
+
+// Connections idle for longer than this are probed before being reused.
+const CHECK_AFTER: Duration = Duration::from_secs(1);
+const CHECK_TIMEOUT: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone)]
+pub struct KvsPool {
+    addr: SocketAddr,
+    idle: Arc<Mutex<Vec<(Connection, Instant)>>>,
+    permits: Arc<Semaphore>
+}
+
+impl KvsPool {
+    pub fn new(addr: SocketAddr, size: usize) -> KvsPool {
+        KvsPool {
+            addr,
+            idle: Arc::new(Mutex::new(Vec::new())),
+            permits: Arc::new(Semaphore::new(size))
+        }
+    }
+
+    pub fn idle_connections(&self) -> usize {
+        self.idle.lock().unwrap().len()
+    }
+
+    pub async fn request(&self, frame: &Frame) -> crate::Result<Frame> {
+        let _permit = self.permits.acquire().await?;
+        let mut connection = self.checkout().await?;
+        connection.write_frame(frame).await?;
+
+        // Only a connection that answered goes back to the pool.
+        match connection.read().await? {
+            Some(Frame::Error(msg)) => {
+                self.checkin(connection);
+                Err(msg.into())
+            },
+            Some(frame) => {
+                self.checkin(connection);
+                Ok(frame)
+            },
+            None => Err("Connection closed by the peer".into())
+        }
+    }
+
+    async fn checkout(&self) -> crate::Result<Connection> {
+        loop {
+            let idle = self.idle.lock().unwrap().pop();
+            match idle {
+                Some((connection, since)) if since.elapsed() < CHECK_AFTER => return Ok(connection),
+                Some((mut connection, _)) => {
+                    if is_healthy(&mut connection).await {
+                        return Ok(connection);
+                    }
+                    info!("Dropping a broken connection to {}", self.addr);
+                },
+                None => {
+                    let socket = TcpStream::connect(self.addr).await?;
+                    return Ok(Connection::new(socket));
+                }
+            }
+        }
+    }
+
+    fn checkin(&self, connection: Connection) {
+        self.idle.lock().unwrap().push((connection, Instant::now()));
+    }
+}
+
+async fn is_healthy(connection: &mut Connection) -> bool {
+    let probe = Frame::Array(vec![
+        Frame::Simple("get".to_string()),
+        Frame::Simple("gandalf:health".to_string())
+    ]);
+    let check = async {
+        connection.write_frame(&probe).await.is_ok() && matches!(connection.read().await, Ok(Some(_)))
+    };
+    time::timeout(CHECK_TIMEOUT, check).await.unwrap_or(false)
+}
@@ -1,49 +1,186 @@
-use gandalf_kvs::{Frame, Connection};
+use gandalf_kvs::Frame;
 
-use tokio::io::AsyncReadExt;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
 use tokio::fs::File;
 
-use crate::Tracker;
-use crate::tracker::{Index, Term};
+use crate::{Tracker, SESSION_EXPIRY};
+use crate::tracker::{Index, Term, LogEntry};
+use crate::parser::Session;
+use crate::storage::{Wal, FsyncPolicy};
+use crate::client::kvs::pool::{KvsPool, POOL_SIZE};
 
-use tokio::net::TcpStream;
 use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
 
-use std::fs::OpenOptions;
+use std::fs::{self, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::collections::HashMap;
+
+use serde::{Serialize, Deserialize};
+
+use tracing::{info, error};
 
 
 #[derive(Debug, Clone)]
-pub struct Cell(Term, Frame);
+pub struct Cell(Term, LogEntry<Frame>);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SessionEntry {
+    sequence: u64,
+    response: Frame,
+    #[serde(default)]
+    last_index: Index
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SnapshotData {
+    frame: Frame,
+    #[serde(default)]
+    sessions: HashMap<String, SessionEntry>
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SnapshotMeta {
+    last_included_index: Index,
+    last_included_term: Term
+}
 
 #[derive(Debug, Clone)]
 pub struct KvsTracker {
     log: Vec<Cell>,
     last_log_index: Index,
     snapshot_no: u64,
-    snapshot_offset: u64,
     last_log_term: Term,
     last_commited_index: Index,
-    addr: SocketAddr,
+    pool: KvsPool,
     snapshot_path: String,
     last_snapshot_term: Term,
     last_snapshot_index: Index,
+    staged: Option<(Term, Index, u64)>,
+    sessions: HashMap<String, SessionEntry>,
+    session_expiry: Index,
+    wal: Arc<Mutex<Wal<LogEntry<Frame>>>>
 }
 
 impl KvsTracker {
-    pub fn new(addr: SocketAddr, snapshot_path: String, snapshot_offset: u64) -> KvsTracker {
-        KvsTracker {
-            log: Vec::new(),
-            last_log_index: 0,
-            last_log_term: 0,
-            last_commited_index: 0,
+    pub fn new(addr: SocketAddr, snapshot_path: String, fsync: FsyncPolicy)
+        -> crate::Result<KvsTracker> {
+        let (wal, records) = Wal::open(format!("{}/wal", snapshot_path), fsync)?;
+
+        let (last_log_index, last_log_term) = match records.last() {
+            Some((index, term, _)) => (*index, *term),
+            None => (0, 0)
+        };
+        let last_snapshot_index = match records.first() {
+            Some((index, _, _)) => index - 1,
+            None => 0
+        };
+        let log = records.into_iter()
+            .map(|(_, term, frame)| Cell(term, frame))
+            .collect();
+
+        Ok(KvsTracker {
+            log,
+            last_log_index,
+            last_log_term,
+            last_commited_index: last_snapshot_index,
             snapshot_no: 0,
-            snapshot_offset,
-            addr,
+            pool: KvsPool::new(addr, POOL_SIZE),
             snapshot_path,
             last_snapshot_term: 0,
-            last_snapshot_index: 0
+            last_snapshot_index,
+            staged: None,
+            sessions: HashMap::new(),
+            session_expiry: SESSION_EXPIRY.parse()?,
+            wal: Arc::new(Mutex::new(wal))
+        })
+    }
+
+    // Must be the same on every node, replicas evict by log index alone.
+    pub fn set_session_expiry(&mut self, entries: Index) {
+        self.session_expiry = entries;
+    }
+
+    // Installed snapshots keep the leader's numbering, so a follower may hold
+    // an older snapshot under a higher number. Pick by the index it covers.
+    fn latest_snapshot(&self) -> Option<(u64, SnapshotData, SnapshotMeta)> {
+        let mut snapshots: Vec<(u64, SnapshotMeta)> = fs::read_dir(&self.snapshot_path).ok()?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().and_then(|ext| ext.to_str()) == Some("ga"))
+            .filter_map(|path| path.file_stem()?.to_str()?.parse().ok())
+            .filter_map(|no| {
+                let meta = fs::File::open(format!("{}/{}.meta", self.snapshot_path, no)).ok()
+                    .and_then(|file| serde_json::from_reader::<_, SnapshotMeta>(file).ok());
+                if meta.is_none() {
+                    error!("Skipping invalid snapshot {}", no);
+                }
+                Some((no, meta?))
+            })
+            .collect();
+        snapshots.sort_unstable_by_key(|(no, meta)| (meta.last_included_index, *no));
+
+        for (no, meta) in snapshots.into_iter().rev() {
+            let data = fs::File::open(format!("{}/{}.ga", self.snapshot_path, no)).ok()
+                .and_then(|file| parse_snapshot(file).ok());
+            match data {
+                Some(data) => return Some((no, data, meta)),
+                None => error!("Skipping invalid snapshot {}", no)
+            }
+        }
+        None
+    }
+
+    fn write_snapshot(&self, no: u64, data: &SnapshotData, index: Index, term: Term) -> crate::Result<()> {
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(format!("{}/{}.ga", self.snapshot_path, no))?;
+        serde_json::to_writer(&file, data)?;
+        file.sync_all()?;
+
+        let meta = SnapshotMeta { last_included_index: index, last_included_term: term };
+        let tmp = format!("{}/{}.meta.tmp", self.snapshot_path, no);
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&tmp)?;
+        serde_json::to_writer(&file, &meta)?;
+        file.sync_all()?;
+        fs::rename(tmp, format!("{}/{}.meta", self.snapshot_path, no))?;
+        Ok(())
+    }
+
+    // A retried write gets the response of its first apply, while anything
+    // older than the last sequence of its session is rejected.
+    fn cached_response(&self, session: &Session) -> Option<Frame> {
+        let entry = self.sessions.get(&session.client_id)?;
+        if session.sequence == entry.sequence {
+            Some(entry.response.clone())
+        } else if session.sequence < entry.sequence {
+            Some(Frame::Error(format!("ERR stale sequence number {}", session.sequence)))
+        } else {
+            None
         }
     }
+
+    // Every session_expiry entries, drop the sessions that have not written
+    // since the previous sweep.
+    fn expire_sessions(&mut self, index: Index) {
+        if self.session_expiry == 0 || !index.is_multiple_of(self.session_expiry) {
+            return;
+        }
+        let expiry = self.session_expiry;
+        self.sessions.retain(|_, entry| entry.last_index + expiry > index);
+    }
+
+    fn read_commit_index(&self) -> Index {
+        fs::read_to_string(format!("{}/commit", self.snapshot_path)).ok()
+            .and_then(|index| index.trim().parse().ok())
+            .unwrap_or(0)
+    }
 }
 
 #[tonic::async_trait]
@@ -51,13 +188,7 @@ impl Tracker for KvsTracker {
     type Entity = Frame;
 
     async fn propagate(&self, entity: &Self::Entity) -> crate::Result<Self::Entity> {
-        let socket = TcpStream::connect(self.addr).await?;
-        let mut connection = Connection::new(socket);
-        connection.write_frame(entity).await?;
-
-        let response = read_response(&mut connection).await?;
-
-        Ok(response)
+        self.pool.request(entity).await
     }
 
     fn get_last_log_index(&self) -> Index {
@@ -72,7 +203,7 @@ impl Tracker for KvsTracker {
         self.last_commited_index
     } 
 
-    fn get_log_entity(&self, index: Index) -> &Self::Entity {
+    fn get_log_entity(&self, index: Index) -> &LogEntry<Self::Entity> {
         let i = index - 1 - self.get_last_snapshot_index();
         &self.log[i as usize].1
     }
@@ -105,115 +236,219 @@ impl Tracker for KvsTracker {
         self.snapshot_no
     }
 
-    fn append_log(&mut self, entity: Self::Entity, term: Term) -> crate::Result<Index> {
+    fn append_log(&mut self, entity: LogEntry<Self::Entity>, term: Term) -> crate::Result<Index> {
+        let index = self.last_log_index + 1;
+        self.wal.lock().unwrap().append(index, term, &entity)?;
         self.last_log_term = term;
         self.log.push(Cell(term, entity));
-        self.last_log_index += 1;
+        self.last_log_index = index;
         Ok(self.last_log_index)
     }
 
     fn delete_last_log(&mut self) -> crate::Result<()> {
-        if let None = self.log.pop() {
+        if self.log.is_empty() {
             return Err("The log is empty".into());
         }
+        self.truncate_log(self.last_log_index)
+    }
+
+    fn truncate_log(&mut self, from: Index) -> crate::Result<()> {
+        if from <= self.last_commited_index {
+            return Err(format!("Could not truncate the commited index {}", from).into());
+        }
+        if from > self.last_log_index {
+            return Ok(());
+        }
+        self.wal.lock().unwrap().truncate(from)?;
+        self.log.truncate((from - 1 - self.last_snapshot_index) as usize);
+        self.last_log_index = from - 1;
+        self.last_log_term = self.get_log_term(self.last_log_index);
         Ok(())
     }
 
     async fn take_snapshot(&mut self) -> crate::Result<()> {
-        let socket = TcpStream::connect(self.addr).await?;
-        let mut connection = Connection::new(socket);
         let snap = Frame::Array(vec![Frame::Simple("snap".to_string())]);
-        connection.write_frame(&snap).await?;
+        let frame = self.pool.request(&snap).await?;
 
-        let response = read_response(&mut connection).await?;
+        let snapshot_index = self.last_commited_index;
+        let snapshot_term = self.get_log_term(snapshot_index);
+        let compacted = snapshot_index - self.last_snapshot_index;
 
-        match response {
-            frame => {
-                let file = OpenOptions::new()
-                    .create(true)
-                    .write(true)
-                    .truncate(true)
-                    .open(format!("{}/{}.ga", self.snapshot_path, self.snapshot_no))?;
-                serde_json::to_writer(file, &frame)?;
-            }
-        }
+        let data = SnapshotData { frame, sessions: self.sessions.clone() };
+        self.write_snapshot(self.snapshot_no, &data, snapshot_index, snapshot_term)?;
 
         self.snapshot_no += 1;
-        self.last_snapshot_term = self.get_last_log_term();
-        self.last_snapshot_index = self.last_commited_index;
-        for _ in 0..self.snapshot_offset {
-            self.log.pop();
-        };
+        self.log.drain(..compacted as usize);
+        self.last_snapshot_term = snapshot_term;
+        self.last_snapshot_index = snapshot_index;
+        self.wal.lock().unwrap().compact(snapshot_index)?;
         Ok(())
     }
 
-    async fn load_snapshot(&mut self, entity: &Self::Entity, last_log_term: Term, last_log_index: Index, offset: u64) 
+    fn stage_snapshot(&mut self, last_log_term: Term, last_log_index: Index, offset: u64, data: &[u8])
+        -> crate::Result<u64> {
+        let staged = match self.staged {
+            Some((term, index, len)) if term == last_log_term && index == last_log_index => len,
+            _ => 0
+        };
+        if offset != staged {
+            return Ok(staged);
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(offset == 0)
+            .open(format!("{}/snapshot.tmp", self.snapshot_path))?;
+        file.seek(SeekFrom::Start(offset))?;
+        file.write_all(data)?;
+        file.sync_all()?;
+        let len = offset + data.len() as u64;
+        self.staged = Some((last_log_term, last_log_index, len));
+        Ok(len)
+    }
+
+    async fn load_snapshot(&mut self, last_log_term: Term, last_log_index: Index, snapshot_no: u64)
         -> crate::Result<()> {
-        let socket = TcpStream::connect(self.addr).await?;
-        let mut connection = Connection::new(socket);
-        connection.write_frame(entity).await?;
+        if snapshot_no == 0 {
+            return Err("Invalid snapshot number 0".into());
+        }
+        let tmp = format!("{}/snapshot.tmp", self.snapshot_path);
+        let data = parse_snapshot(fs::File::open(&tmp)?)?;
 
-        let response = read_response(&mut connection).await?;
+        let response = self.pool.request(&data.frame).await?;
 
         match response {
             Frame::Simple(_) => {
-                let file = OpenOptions::new()
-                    .create(true)
-                    .write(true)
-                    .truncate(true)
-                    .open(format!("{}/{}.ga", self.snapshot_path, offset))?;
-                serde_json::to_writer(file, entity)?;
+                self.write_snapshot(snapshot_no - 1, &data, last_log_index, last_log_term)?;
+                self.sessions = data.sessions;
                 self.last_log_index = last_log_index;
                 self.last_log_term = last_log_term;
                 self.last_snapshot_term = last_log_term;
                 self.last_snapshot_index = last_log_index;
                 self.last_commited_index = last_log_index;
-                self.snapshot_no = offset;
+                self.snapshot_no = snapshot_no;
+                self.staged = None;
 
                 self.log.clear();
+                self.wal.lock().unwrap().reset()?;
+                self.save_commit_index()?;
+                fs::remove_file(tmp)?;
             },
-            _ => unreachable!()
+            frame => return Err(format!("Could not load the snapshot {:?}", frame).into())
         }
         Ok(())
     }
 
-    async fn read_snapshot(&self) -> crate::Result<String> {
+    async fn read_snapshot(&self, offset: u64, len: u64) -> crate::Result<(Vec<u8>, bool)> {
+        if self.snapshot_no == 0 {
+            return Err("No snapshot has been taken yet".into());
+        }
         let mut f = File::open(format!("{}/{}.ga", self.snapshot_path, self.snapshot_no - 1)).await?;
-        let mut dst = String::new();
-        f.read_to_string(&mut dst).await?;
-        return Ok(dst);
+        let size = f.metadata().await?.len();
+        f.seek(SeekFrom::Start(offset)).await?;
+        let mut dst = vec![0; std::cmp::min(len, size.saturating_sub(offset)) as usize];
+        f.read_exact(&mut dst).await?;
+        let done = offset + dst.len() as u64 >= size;
+        Ok((dst, done))
+    }
+
+    async fn recover(&mut self) -> crate::Result<()> {
+        if let Some((no, data, meta)) = self.latest_snapshot() {
+            info!("Recovering from snapshot {} at index {}", no, meta.last_included_index);
+            match self.propagate(&data.frame).await? {
+                Frame::Simple(_) => {},
+                frame => return Err(format!("Could not load the snapshot {:?}", frame).into())
+            }
+
+            let snapshot_index = meta.last_included_index;
+            if snapshot_index < self.last_snapshot_index {
+                return Err("The log is missing entries after the latest snapshot".into());
+            }
+            let compacted = std::cmp::min(snapshot_index - self.last_snapshot_index, self.log.len() as u64);
+            self.log.drain(..compacted as usize);
+            self.wal.lock().unwrap().compact(snapshot_index)?;
+            if self.last_log_index <= snapshot_index {
+                self.wal.lock().unwrap().reset()?;
+                self.last_log_index = snapshot_index;
+                self.last_log_term = meta.last_included_term;
+            }
+
+            self.snapshot_no = no + 1;
+            self.last_snapshot_index = snapshot_index;
+            self.last_snapshot_term = meta.last_included_term;
+            self.last_commited_index = snapshot_index;
+            self.sessions = data.sessions;
+        }
+
+        let commit_index = std::cmp::min(self.read_commit_index(), self.last_log_index);
+        for i in self.last_commited_index..commit_index {
+            self.commit(i).await?;
+        }
+        info!("Recovered up to index {}", self.last_commited_index);
+        Ok(())
     }
 
-    async fn commit(&mut self, index: Index) -> crate::Result<Self::Entity> {
+    async fn commit(&mut self, index: Index) -> crate::Result<LogEntry<Self::Entity>> {
         let i = index - self.get_last_snapshot_index();
         if index + 1 != self.last_commited_index + 1 {
             return Err("Wrong commit index".into());
         }
+        self.expire_sessions(index + 1);
+
+        let (frame, session) = match &self.log[i as usize].1 {
+            LogEntry::Normal(frame) => (frame.clone(), None),
+            LogEntry::Session{session, entity} => (entity.clone(), Some(session.clone())),
+            entry => {
+                let entry = entry.clone();
+                self.last_commited_index += 1;
+                return Ok(entry);
+            }
+        };
+
+        if let Some(response) = session.as_ref().and_then(|session| self.cached_response(session)) {
+            self.last_commited_index += 1;
+            return Ok(LogEntry::Normal(response));
+        }
 
-        let response = self.propagate(&self.log[i as usize].1).await?;
+        let response = self.propagate(&frame).await?;
 
         match response {
-            Frame::Simple(_) => {
-                self.last_commited_index += 1;
-                Ok(response)
-            },
-            Frame::Bulk(_) =>{
+            Frame::Simple(_) | Frame::Bulk(_) => {
                 self.last_commited_index += 1;
-                Ok(response)
+                if let Some(session) = session {
+                    self.sessions.insert(session.client_id, SessionEntry {
+                        sequence: session.sequence,
+                        response: response.clone(),
+                        last_index: self.last_commited_index
+                    });
+                }
+                Ok(LogEntry::Normal(response))
             },
             frame => Err(format!("{:?}", frame).into()),
         }
     }
-}
 
-async fn read_response(connection: &mut Connection) -> crate::Result<Frame> {
-    let response = connection.read().await?;
+    // Only a hint for recover, entries past it get commited again once the
+    // leader sends its commit index.
+    fn save_commit_index(&self) -> crate::Result<()> {
+        let tmp = format!("{}/commit.tmp", self.snapshot_path);
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&tmp)?;
+        file.write_all(self.last_commited_index.to_string().as_bytes())?;
+        file.sync_all()?;
+        fs::rename(tmp, format!("{}/commit", self.snapshot_path))?;
+        Ok(())
+    }
+}
 
-    match response {
-        Some(Frame::Error(msg)) => Err(msg.into()),
-        Some(frame) => Ok(frame),
-        None => {
-            return Err("Connection closed by the peer".into());
-        }
+// Snapshots taken before client sessions hold the bare frame.
+fn parse_snapshot(reader: impl Read) -> crate::Result<SnapshotData> {
+    let value: serde_json::Value = serde_json::from_reader(reader)?;
+    match serde_json::from_value::<SnapshotData>(value.clone()) {
+        Ok(data) => Ok(data),
+        Err(_) => Ok(SnapshotData { frame: serde_json::from_value(value)?, sessions: HashMap::new() })
     }
 }
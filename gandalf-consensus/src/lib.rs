@@ -1,11 +1,22 @@
 use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
 use tokio::sync::oneshot;
-use std::collections::{HashSet, BTreeMap};
+use std::collections::{HashSet, HashMap, BTreeMap};
 use serde::{de::DeserializeOwned, Serialize};
+use parser::Session;
 
 pub const DEFAULT_PORT: &str = "7899";
 pub const HEARTBEAT: &str = "500";
 pub const TIMEOUT: &str = "1500";
+pub const FSYNC: &str = "always";
+pub const CLOCK_DRIFT: &str = "100";
+pub const MAX_BATCH_ENTRIES: &str = "64";
+pub const MAX_BATCH_SIZE: &str = "1048576";
+pub const MAX_INFLIGHT: &str = "4";
+pub const SNAPSHOT_CHUNK_SIZE: &str = "65536";
+pub const CLIENT_TIMEOUT: &str = "5000";
+pub const SESSION_EXPIRY: &str = "10000";
+pub const SNAPSHOT_PATH: &str = "/tmp";
 
 pub mod raft_rpc {
     tonic::include_proto!("raft_rpc");
@@ -16,15 +27,23 @@ pub use raft::Raft;
 
 pub mod rpc;
 
+pub mod admin;
+
 pub mod tracker;
 pub use tracker::Tracker;
 
 pub mod parser;
 
+pub mod storage;
+
 pub mod client;
 
 pub mod server;
 
+pub mod tls;
+
+pub mod metrics;
+
 pub mod state_machine;
 
 pub type Error = Box<dyn std::error::Error + Send + Sync>;
@@ -58,6 +77,14 @@ pub enum RaftMessage<T: ClientData> {
         payload: raft_rpc::RequestVoteResponse,
         status: Option<tonic::Status>
     },
+    PreVoteMsg {
+        body: raft_rpc::PreVoteRequest,
+        tx: oneshot::Sender<RaftMessage<T>>
+    },
+    PreVoteResp {
+        payload: raft_rpc::PreVoteResponse,
+        status: Option<tonic::Status>
+    },
     AppendMsg {
         body: raft_rpc::AppendEntriesRequest,
         tx: oneshot::Sender<RaftMessage<T>>
@@ -72,6 +99,7 @@ pub enum RaftMessage<T: ClientData> {
     },
     ClientWriteMsg {
         body: T,
+        session: Option<Session>,
         tx: oneshot::Sender<RaftMessage<T>>
     },
     ClientResp {
@@ -80,7 +108,51 @@ pub enum RaftMessage<T: ClientData> {
     ClientError {
         body: String
     },
+    ClientRedirect {
+        leader: String
+    },
     SnapMsg,
+    ReadIndexMsg {
+        body: raft_rpc::ReadIndexRequest,
+        tx: oneshot::Sender<RaftMessage<T>>
+    },
+    ReadIndexResp {
+        payload: Option<raft_rpc::ReadIndexResponse>,
+        status: Option<tonic::Status>
+    },
+    ReadReady {
+        read_index: u64,
+        body: T,
+        tx: oneshot::Sender<RaftMessage<T>>
+    },
+    AddServerMsg {
+        body: raft_rpc::AddServerRequest,
+        tx: oneshot::Sender<RaftMessage<T>>
+    },
+    RemoveServerMsg {
+        body: raft_rpc::RemoveServerRequest,
+        tx: oneshot::Sender<RaftMessage<T>>
+    },
+    MembershipResp {
+        payload: Option<raft_rpc::MembershipResponse>,
+        status: Option<tonic::Status>
+    },
+    TransferLeaderMsg {
+        body: raft_rpc::TransferLeadershipRequest,
+        tx: oneshot::Sender<RaftMessage<T>>
+    },
+    TransferLeaderResp {
+        payload: Option<raft_rpc::TransferLeadershipResponse>,
+        status: Option<tonic::Status>
+    },
+    TimeoutNowMsg {
+        body: raft_rpc::TimeoutNowRequest,
+        tx: oneshot::Sender<RaftMessage<T>>
+    },
+    TimeoutNowResp {
+        payload: raft_rpc::TimeoutNowResponse,
+        status: Option<tonic::Status>
+    },
     InstallSnapshot {
         body: raft_rpc::SnapshotRequest,
         tx: oneshot::Sender<RaftMessage<T>>
@@ -88,6 +160,19 @@ pub enum RaftMessage<T: ClientData> {
     InstallSnapshotResp {
         payload: raft_rpc::SnapshotResponse,
         status: Option<tonic::Status>
+    },
+    StatusMsg {
+        tx: oneshot::Sender<RaftMessage<T>>
+    },
+    StatusResp {
+        payload: raft_rpc::StatusResponse
+    },
+    TakeSnapshotMsg {
+        tx: oneshot::Sender<RaftMessage<T>>
+    },
+    TakeSnapshotResp {
+        payload: Option<raft_rpc::TakeSnapshotResponse>,
+        status: Option<tonic::Status>
     }
 }
 
@@ -97,10 +182,31 @@ pub struct ConfigMap {
     pub connecntion_host: String,
     pub connecntion_port: u16,
     nodes: HashSet<Node>,
+    learners: HashSet<Node>,
     nodes_state: BTreeMap<NodeID, NodeState>,
     heartbeat: u64,
     timeout: u64,
-    snapshot_offset: u64
+    snapshot_offset: u64,
+    pub follower_read_index: bool,
+    pub lease_read: bool,
+    pub redirect: bool,
+    pub clock_drift: u64,
+    pub learner: bool,
+    pub max_batch_entries: u64,
+    pub max_batch_size: u64,
+    pub max_inflight: u64,
+    pub snapshot_chunk_size: u64,
+    pub snapshot_path: String,
+    pub client_timeout: u64,
+    pub tls_ca: Option<String>,
+    pub tls_cert: Option<String>,
+    pub tls_key: Option<String>,
+    pub client_tls_cert: Option<String>,
+    pub client_tls_key: Option<String>,
+    pub users: HashMap<String, String>,
+    pub metrics_addr: Option<String>,
+    pub admin_addr: Option<String>,
+    pub admin_token: Option<String>
 }
 
 impl Node {
@@ -109,6 +215,16 @@ impl Node {
     }
 }
 
+impl FromStr for Node {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Node> {
+        let addr: SocketAddr = s.parse()?;
+        let id = format!("{}:{}", addr.ip(), addr.port());
+        Ok(Node::new(id, addr.ip(), addr.port()))
+    }
+}
+
 impl NodeState {
     pub fn new(match_index: u64, next_index: u64) -> NodeState {
         NodeState { match_index, next_index }
@@ -124,24 +240,62 @@ impl ConfigMap {
         let mut nodes_state = BTreeMap::new();
 
         for node_raw in nodes_raw.into_iter() {
-            let node: SocketAddr = node_raw.parse()?;
-            let id = format!("{}:{}", node.ip(), node.port());
+            let node: Node = node_raw.parse()?;
             let node_state = NodeState::new(0, 0);
-            nodes.insert(Node::new(id.clone(), node.ip(), node.port()));
-            nodes_state.insert(id, node_state);
+            nodes_state.insert(node.id.clone(), node_state);
+            nodes.insert(node);
         }
 
         Ok(ConfigMap {
             host,
             port,
             nodes,
+            learners: HashSet::new(),
             heartbeat,
             timeout,
             nodes_state,
             connecntion_port,
             connecntion_host,
-            snapshot_offset
+            snapshot_offset,
+            follower_read_index: false,
+            lease_read: false,
+            redirect: false,
+            clock_drift: CLOCK_DRIFT.parse()?,
+            learner: false,
+            max_batch_entries: MAX_BATCH_ENTRIES.parse()?,
+            max_batch_size: MAX_BATCH_SIZE.parse()?,
+            max_inflight: MAX_INFLIGHT.parse()?,
+            snapshot_chunk_size: SNAPSHOT_CHUNK_SIZE.parse()?,
+            snapshot_path: SNAPSHOT_PATH.to_string(),
+            client_timeout: CLIENT_TIMEOUT.parse()?,
+            tls_ca: None,
+            tls_cert: None,
+            tls_key: None,
+            client_tls_cert: None,
+            client_tls_key: None,
+            users: HashMap::new(),
+            metrics_addr: None,
+            admin_addr: None,
+            admin_token: None
         })
 
     }
+
+    pub fn set_learners(&mut self, learners_raw: Vec<String>) -> Result<()> {
+        for learner_raw in learners_raw.into_iter() {
+            let node: Node = learner_raw.parse()?;
+            self.nodes_state.insert(node.id.clone(), NodeState::new(0, 0));
+            self.learners.insert(node);
+        }
+        Ok(())
+    }
+
+    pub fn set_users(&mut self, users_raw: Vec<String>) -> Result<()> {
+        for user_raw in users_raw.into_iter() {
+            let (user, password) = user_raw.split_once(':')
+                .ok_or_else(|| format!("Expected user:password, got {}", user_raw))?;
+            self.users.insert(user.to_string(), password.to_string());
+        }
+        Ok(())
+    }
 }
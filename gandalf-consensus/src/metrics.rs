@@ -0,0 +1,187 @@
+use prometheus::{Encoder, TextEncoder, Registry, Opts, HistogramOpts};
+use prometheus::{IntGauge, IntGaugeVec, IntCounter, IntCounterVec, HistogramVec};
+
+use hyper::{Body, Request, Response, Server, StatusCode};
+use hyper::header::CONTENT_TYPE;
+use hyper::service::{make_service_fn, service_fn};
+
+use std::convert::Infallible;
+use std::fmt;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use crate::NodeState;
+use crate::raft::State;
+
+const STATES: [(&str, State); 4] = [
+    ("follower", State::Follower),
+    ("candidate", State::Candidate),
+    ("leader", State::Leader),
+    ("non_voter", State::NonVoter)
+];
+
+// Every node keeps its own registry, so several nodes can share a process.
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    current_term: IntGauge,
+    state: IntGaugeVec,
+    commit_index: IntGauge,
+    last_applied: IntGauge,
+    last_log_index: IntGauge,
+    match_index: IntGaugeVec,
+    next_index: IntGaugeVec,
+    follower_lag: IntGaugeVec,
+    elections: IntCounter,
+    elections_won: IntCounter,
+    snapshots: IntCounterVec,
+    snapshot_duration: HistogramVec,
+    client_requests: HistogramVec
+}
+
+impl Metrics {
+    pub fn new() -> crate::Result<Metrics> {
+        let registry = Registry::new_custom(Some("gandalf".to_string()), None)?;
+        let peer = &["peer"];
+        let kind = &["kind"];
+        let metrics = Metrics {
+            current_term: IntGauge::new("current_term", "Current raft term")?,
+            state: IntGaugeVec::new(Opts::new("state", "1 for the state the node is in"), &["state"])?,
+            commit_index: IntGauge::new("commit_index", "Highest log index known to be committed")?,
+            last_applied: IntGauge::new("last_applied_index", "Highest log index applied to the tracker")?,
+            last_log_index: IntGauge::new("last_log_index", "Index of the last log entry")?,
+            match_index: IntGaugeVec::new(Opts::new("follower_match_index",
+                "Highest index known to be replicated on a follower"), peer)?,
+            next_index: IntGaugeVec::new(Opts::new("follower_next_index",
+                "Next index the leader sends to a follower"), peer)?,
+            follower_lag: IntGaugeVec::new(Opts::new("follower_lag",
+                "Entries the leader has that a follower has not matched"), peer)?,
+            elections: IntCounter::new("elections_started_total", "Elections started by this node")?,
+            elections_won: IntCounter::new("elections_won_total", "Elections won by this node")?,
+            snapshots: IntCounterVec::new(Opts::new("snapshots_total",
+                "Snapshots taken locally or installed from the leader"), kind)?,
+            snapshot_duration: HistogramVec::new(HistogramOpts::new("snapshot_duration_seconds",
+                "Time spent taking or installing a snapshot"), kind)?,
+            client_requests: HistogramVec::new(HistogramOpts::new("client_request_duration_seconds",
+                "Client request latency from parse to response"), kind)?,
+            registry
+        };
+        metrics.registry.register(Box::new(metrics.current_term.clone()))?;
+        metrics.registry.register(Box::new(metrics.state.clone()))?;
+        metrics.registry.register(Box::new(metrics.commit_index.clone()))?;
+        metrics.registry.register(Box::new(metrics.last_applied.clone()))?;
+        metrics.registry.register(Box::new(metrics.last_log_index.clone()))?;
+        metrics.registry.register(Box::new(metrics.match_index.clone()))?;
+        metrics.registry.register(Box::new(metrics.next_index.clone()))?;
+        metrics.registry.register(Box::new(metrics.follower_lag.clone()))?;
+        metrics.registry.register(Box::new(metrics.elections.clone()))?;
+        metrics.registry.register(Box::new(metrics.elections_won.clone()))?;
+        metrics.registry.register(Box::new(metrics.snapshots.clone()))?;
+        metrics.registry.register(Box::new(metrics.snapshot_duration.clone()))?;
+        metrics.registry.register(Box::new(metrics.client_requests.clone()))?;
+        Ok(metrics)
+    }
+
+    pub fn set_term(&self, term: u64) {
+        self.current_term.set(term as i64);
+    }
+
+    pub fn set_state(&self, state: &State) {
+        for (name, value) in STATES.iter() {
+            self.state.with_label_values(&[name]).set((value == state) as i64);
+        }
+    }
+
+    pub fn set_commit_index(&self, commit_index: u64) {
+        self.commit_index.set(commit_index as i64);
+    }
+
+    pub fn set_last_applied(&self, last_applied: u64) {
+        self.last_applied.set(last_applied as i64);
+    }
+
+    pub fn set_last_log_index(&self, index: u64) {
+        self.last_log_index.set(index as i64);
+    }
+
+    pub fn set_follower(&self, id: &str, state: &NodeState, last_log_index: u64) {
+        self.match_index.with_label_values(&[id]).set(state.match_index as i64);
+        self.next_index.with_label_values(&[id]).set(state.next_index as i64);
+        self.follower_lag.with_label_values(&[id]).set(last_log_index.saturating_sub(state.match_index) as i64);
+    }
+
+    pub fn remove_follower(&self, id: &str) {
+        let _ = self.match_index.remove_label_values(&[id]);
+        let _ = self.next_index.remove_label_values(&[id]);
+        let _ = self.follower_lag.remove_label_values(&[id]);
+    }
+
+    // Only the leader tracks its followers.
+    pub fn clear_followers(&self) {
+        self.match_index.reset();
+        self.next_index.reset();
+        self.follower_lag.reset();
+    }
+
+    pub fn election_started(&self) {
+        self.elections.inc();
+    }
+
+    pub fn election_won(&self) {
+        self.elections_won.inc();
+    }
+
+    pub fn observe_snapshot(&self, kind: &str, duration: Duration) {
+        self.snapshots.with_label_values(&[kind]).inc();
+        self.snapshot_duration.with_label_values(&[kind]).observe(duration.as_secs_f64());
+    }
+
+    pub fn observe_request(&self, kind: &str, duration: Duration) {
+        self.client_requests.with_label_values(&[kind]).observe(duration.as_secs_f64());
+    }
+
+    pub fn encode(&self) -> crate::Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buf)?;
+        Ok(buf)
+    }
+
+    fn response(&self, request: Request<Body>) -> Response<Body> {
+        let mut response = Response::new(Body::empty());
+        if request.uri().path() != "/metrics" {
+            *response.status_mut() = StatusCode::NOT_FOUND;
+            return response;
+        }
+        match self.encode() {
+            Ok(buf) => {
+                response.headers_mut().insert(CONTENT_TYPE, TextEncoder::new().format_type().parse().unwrap());
+                *response.body_mut() = Body::from(buf);
+            },
+            Err(err) => {
+                *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+                *response.body_mut() = Body::from(err.to_string());
+            }
+        }
+        response
+    }
+}
+
+impl fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Metrics").finish()
+    }
+}
+
+pub async fn serve(addr: SocketAddr, metrics: Metrics) -> crate::Result<()> {
+    let make_svc = make_service_fn(move |_| {
+        let metrics = metrics.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let response = metrics.response(request);
+                async move { Ok::<_, Infallible>(response) }
+            }))
+        }
+    });
+    Server::try_bind(&addr)?.serve(make_svc).await?;
+    Ok(())
+}
@@ -1,10 +1,20 @@
 use bytes::{Bytes, BytesMut};
+use serde::{Serialize, Deserialize};
 
 use crate::ClientData;
 
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Session {
+    pub client_id: String,
+    pub sequence: u64
+}
+
 pub enum Kind<T: ClientData> {
     Read(T),
-    Write(T)
+    Write(T, Option<Session>),
+    Timeout(u64),
+    Auth(String, String),
+    Invalid(String)
 }
 
 pub trait Parser<T: ClientData>: Send + Sync + Clone + 'static {
@@ -13,5 +23,9 @@ pub trait Parser<T: ClientData>: Send + Sync + Clone + 'static {
     fn unparse(&self, data: T) -> crate::Result<Bytes>;
 
     fn into_error(&self, data: &str) -> crate::Result<Bytes>;
+
+    fn into_ok(&self) -> crate::Result<Bytes>;
+
+    fn into_redirect(&self, leader: &str) -> crate::Result<Bytes>;
     
 }
@@ -4,14 +4,22 @@ use std::sync::Arc;
 use rand::{thread_rng, Rng};
 
 use tokio::time::{Duration, Instant};
-use tokio::sync::{mpsc, RwLock};
+use tokio::sync::{mpsc, oneshot, RwLock};
 
-use tracing::info;
+use tracing::{info, error};
 
 use crate::{NodeID, Node, NodeState, RaftMessage, ConfigMap, ClientData, Tracker};
+use crate::tracker::LogEntry;
 use crate::state_machine::{Follower, Candidate, Leader};
+use crate::storage::{HardState, HardStateStore};
+use crate::rpc::ChannelPool;
+use crate::tls::PeerTls;
+use crate::metrics::Metrics;
 
 use crate::raft_rpc::{RequestVoteRequest, RequestVoteResponse};
+use crate::raft_rpc::{PreVoteRequest, PreVoteResponse};
+use crate::raft_rpc::{MembershipResponse, TransferLeadershipResponse};
+use crate::raft_rpc::{StatusResponse, PeerStatus, TakeSnapshotResponse};
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum State {
@@ -34,48 +42,124 @@ pub struct Raft<T: ClientData, R: Tracker<Entity=T>> {
     pub voted_for: Option<NodeID>,
     pub current_leader: Option<NodeID>,
     pub nodes: HashSet<Node>,
+    pub learners: HashSet<Node>,
+    pub learner: bool,
+    pub timeout_now: bool,
     pub nodes_state: BTreeMap<NodeID, NodeState>,
     pub rx_rpc: mpsc::UnboundedReceiver<RaftMessage<T>>,
     pub rx_snap: mpsc::UnboundedReceiver<RaftMessage<T>>,
     pub tx_snap: mpsc::UnboundedSender<RaftMessage<T>>,
+    pub rx_read: mpsc::UnboundedReceiver<RaftMessage<T>>,
+    pub tx_read: mpsc::UnboundedSender<RaftMessage<T>>,
+    pub follower_read_index: bool,
+    pub lease_read: bool,
+    pub redirect: bool,
+    pub client_addr: String,
+    pub leader_client_addr: Option<(NodeID, String)>,
+    pub clock_drift: u64,
+    pub max_batch_entries: u64,
+    pub max_batch_size: u64,
+    pub max_inflight: u64,
+    pub snapshot_chunk_size: u64,
     pub election_timeout: u64,
     pub heartbeat: Duration,
     pub snapshot_offset: u64,
     pub snapshot_num: u64,
-    pub tracker: Arc<RwLock<R>>
+    pub tracker: Arc<RwLock<R>>,
+    pub peers: ChannelPool,
+    pub tls: Option<PeerTls>,
+    pub metrics: Metrics,
+    hard_state: HardStateStore,
+    configuration: Option<Vec<NodeID>>,
+    last_leader_contact: Option<Instant>,
+    pending_writes: BTreeMap<u64, oneshot::Sender<RaftMessage<T>>>
 }
 
 impl<T: ClientData, R: Tracker<Entity=T>> Raft<T, R> {
     pub fn new(config: ConfigMap, rx_rpc: mpsc::UnboundedReceiver<RaftMessage<T>>,
-        tracker: Arc<RwLock<R>>, id: String) -> Raft<T, R> {
+        tracker: Arc<RwLock<R>>, id: String) -> crate::Result<Raft<T, R>> {
         let (tx_snap, rx_snap) = mpsc::unbounded_channel();
-        Raft {
+        let (tx_read, rx_read) = mpsc::unbounded_channel();
+        let (hard_state, state) = HardStateStore::open(&config.snapshot_path)?;
+        let (commit_index, last_log_index, last_log_term, snapshot_num) = match tracker.try_read() {
+            Ok(tracker) => (tracker.get_last_commited_index(), tracker.get_last_log_index(),
+                tracker.get_last_log_term(), tracker.get_snapshot_no()),
+            Err(_) => (0, 0, 0, 0)
+        };
+        let persisted = state.nodes.clone().map(|nodes| (nodes, state.learners.clone()));
+        let configuration = match tracker.try_read() {
+            Ok(tracker) => (tracker.get_last_snapshot_index() + 1..=commit_index).rev()
+                .find_map(|index| match tracker.get_log_entity(index) {
+                    LogEntry::Configuration{nodes, learners} => Some((nodes.clone(), learners.clone())),
+                    _ => None
+                })
+                .or(persisted),
+            Err(_) => persisted
+        };
+        let tls = match (config.tls_ca, config.tls_cert, config.tls_key) {
+            (Some(ca), Some(cert), Some(key)) => Some(PeerTls::load(ca, cert, key)?),
+            (None, None, None) => None,
+            _ => return Err("tls_ca, tls_cert and tls_key must be set together".into())
+        };
+        let mut raft = Raft {
             id,
-            state: State::Follower,
-            current_term: 0,
-            commit_index: 0,
-            last_applied: 0,
-            last_log_index: 0,
-            last_log_term: 0,
-            voted_for: None,
+            state: if config.learner { State::NonVoter } else { State::Follower },
+            current_term: state.current_term,
+            commit_index,
+            last_applied: commit_index,
+            last_log_index,
+            last_log_term,
+            voted_for: state.voted_for,
             current_leader: None,
             nodes: config.nodes,
+            learners: config.learners,
+            learner: config.learner,
+            timeout_now: false,
             nodes_state: config.nodes_state,
             rx_rpc,
             rx_snap,
             tx_snap,
+            rx_read,
+            tx_read,
+            follower_read_index: config.follower_read_index,
+            lease_read: config.lease_read,
+            redirect: config.redirect,
+            client_addr: format!("{}:{}", config.connecntion_host, config.connecntion_port),
+            leader_client_addr: None,
+            clock_drift: config.clock_drift,
+            max_batch_entries: config.max_batch_entries,
+            max_batch_size: config.max_batch_size,
+            max_inflight: config.max_inflight,
+            snapshot_chunk_size: config.snapshot_chunk_size,
             election_timeout: config.timeout,
             heartbeat: Duration::from_millis(config.heartbeat),
             snapshot_offset: config.snapshot_offset,
-            snapshot_num: 0,
-            tracker
+            snapshot_num,
+            tracker,
+            peers: ChannelPool::new(Duration::from_millis(config.heartbeat), tls.clone()),
+            tls,
+            metrics: Metrics::new()?,
+            hard_state,
+            configuration: None,
+            last_leader_contact: None,
+            pending_writes: BTreeMap::new()
+        };
+        if let Some((nodes, learners)) = configuration {
+            raft.apply_configuration(nodes, learners)?;
         }
+        raft.update_tls_members();
+        raft.metrics.set_term(raft.current_term);
+        raft.metrics.set_state(&raft.state);
+        raft.metrics.set_commit_index(raft.commit_index);
+        raft.metrics.set_last_applied(raft.last_applied);
+        raft.metrics.set_last_log_index(raft.last_log_index);
+        Ok(raft)
     }
 
     pub async fn run(&mut self) -> crate::Result<()> {
         loop {
             match self.state {
-                State::Follower => {
+                State::Follower | State::NonVoter => {
                     Follower::new(self).run().await?;
                 },
                 State::Candidate => {
@@ -84,68 +168,303 @@ impl<T: ClientData, R: Tracker<Entity=T>> Raft<T, R> {
                 State::Leader => {
                     Leader::new(self).run().await?;
                 },
-                State::NonVoter => {
-                    info!("Running at NonVoter State");
-                    return Ok(());
-                }
             }
         }
     }
 
     pub fn handle_vote_request(&mut self, body: RequestVoteRequest) -> RaftMessage<T> {
-        if self.current_term > body.term {
-            return RaftMessage::VoteResp {
-                payload: RequestVoteResponse {
-                    term: self.current_term,
-                    vote_granted: false
-                },
-                status: None
-            }
+        if self.current_term > body.term || self.state == State::NonVoter {
+            return self.vote_response(false);
+        }
+        let (mut term, mut voted_for) = (self.current_term, self.voted_for.clone());
+        if body.term > term {
+            term = body.term;
+            voted_for = None;
+            self.set_state(State::Follower);
         }
-        self.current_term = body.term;
-        if (self.last_term() > body.last_log_term) || (self.last_index() > body.last_log_index) {
+        let up_to_date = self.is_log_up_to_date(body.last_log_term, body.last_log_index);
+        let granted = up_to_date && match &voted_for {
+            Some(candidate_id) => *candidate_id == body.candidate_id,
+            None => true
+        };
+        if granted {
+            voted_for = Some(body.candidate_id);
+            self.set_state(State::Follower);
+        }
+        if let Err(err) = self.update_hard_state(term, voted_for) {
+            error!(cause = %err, "Could not persist the hard state: ");
             return RaftMessage::VoteResp {
                 payload: RequestVoteResponse {
                     term: self.current_term,
                     vote_granted: false
                 },
-                status: None
+                status: Some(tonic::Status::internal("Could not persist the vote"))
             }
         }
-        match &self.voted_for {
-            Some(candidate_id) if candidate_id.to_string() == body.candidate_id => {
-                self.set_state(State::Follower);
-                return RaftMessage::VoteResp {
-                    payload: RequestVoteResponse {
-                        term: self.current_term,
-                        vote_granted: true
-                    },
-                    status: None
-                }
+        self.vote_response(granted)
+    }
+
+    pub fn handle_pre_vote_request(&self, body: PreVoteRequest) -> RaftMessage<T> {
+        let up_to_date = self.is_log_up_to_date(body.last_log_term, body.last_log_index);
+        let vote_granted = body.term > self.current_term && up_to_date &&
+            !matches!(self.state, State::Leader | State::NonVoter) && !self.has_recent_leader();
+        RaftMessage::PreVoteResp {
+            payload: PreVoteResponse {
+                term: self.current_term,
+                vote_granted
             },
-            Some(_) => {
-                return RaftMessage::VoteResp {
-                    payload: RequestVoteResponse {
-                        term: self.current_term,
-                        vote_granted: false
-                    },
-                    status: None
-                }
+            status: None
+        }
+    }
+
+    // A log is at least as up to date as ours if its last term is newer, or
+    // the same with at least as many entries.
+    fn is_log_up_to_date(&self, last_log_term: u64, last_log_index: u64) -> bool {
+        last_log_term > self.last_term() ||
+            (last_log_term == self.last_term() && last_log_index >= self.last_index())
+    }
+
+    pub fn heard_from_leader(&mut self) {
+        self.last_leader_contact = Some(Instant::now());
+    }
+
+    fn has_recent_leader(&self) -> bool {
+        match self.last_leader_contact {
+            Some(contact) => contact.elapsed() < Duration::from_millis(self.election_timeout),
+            None => false
+        }
+    }
+
+    fn vote_response(&self, vote_granted: bool) -> RaftMessage<T> {
+        RaftMessage::VoteResp {
+            payload: RequestVoteResponse {
+                term: self.current_term,
+                vote_granted
             },
-            None => {
-                self.set_state(State::Follower);
-                return RaftMessage::VoteResp {
-                    payload: RequestVoteResponse {
-                        term: self.current_term,
-                        vote_granted: true
-                    },
-                    status: None
+            status: None
+        }
+    }
+
+    pub fn update_hard_state(&mut self, term: u64, voted_for: Option<NodeID>) -> crate::Result<()> {
+        if self.current_term == term && self.voted_for == voted_for {
+            return Ok(());
+        }
+        self.hard_state.save(&HardState {
+            current_term: term,
+            voted_for: voted_for.clone(),
+            nodes: self.configuration.clone(),
+            learners: self.learner_members()
+        })?;
+        self.current_term = term;
+        self.voted_for = voted_for;
+        self.metrics.set_term(term);
+        Ok(())
+    }
+
+    pub fn apply_configuration(&mut self, nodes: Vec<NodeID>, learners: Vec<NodeID>)
+        -> crate::Result<(Vec<Node>, Vec<NodeID>)> {
+        let mut peers = HashSet::new();
+        for id in nodes.iter().filter(|id| **id != self.id) {
+            peers.insert(id.parse::<Node>()?);
+        }
+        let mut learner_peers = HashSet::new();
+        for id in learners.iter().filter(|id| **id != self.id) {
+            learner_peers.insert(id.parse::<Node>()?);
+        }
+        self.hard_state.save(&HardState {
+            current_term: self.current_term,
+            voted_for: self.voted_for.clone(),
+            nodes: Some(nodes.clone()),
+            learners: learners.clone()
+        })?;
+        info!("Applying configuration {:?} with learners {:?}", nodes, learners);
+
+        let before: HashSet<Node> = self.nodes.union(&self.learners).cloned().collect();
+        let after: HashSet<Node> = peers.union(&learner_peers).cloned().collect();
+        let added: Vec<Node> = after.difference(&before).cloned().collect();
+        let removed: Vec<NodeID> = before.difference(&after).map(|node| node.id.clone()).collect();
+        for id in removed.iter() {
+            self.nodes_state.remove(id);
+            self.peers.remove(id);
+            self.metrics.remove_follower(id);
+        }
+        for node in added.iter() {
+            self.nodes_state.insert(node.id.clone(), NodeState::new(0, self.last_index() + 1));
+        }
+        self.nodes = peers;
+        self.learners = learner_peers;
+        self.update_tls_members();
+        self.learner = learners.contains(&self.id);
+        match self.state {
+            State::Follower if self.learner => self.set_state(State::NonVoter),
+            State::NonVoter if nodes.contains(&self.id) => self.set_state(State::Follower),
+            _ => {}
+        }
+        self.configuration = Some(nodes);
+        Ok((added, removed))
+    }
+
+    // Peers connecting over tls must present a certificate for one of the
+    // member ips.
+    fn update_tls_members(&self) {
+        if let Some(tls) = &self.tls {
+            tls.set_members(self.nodes.union(&self.learners).map(|node| node.ip).collect());
+        }
+    }
+
+    pub fn membership_response(&self, success: bool) -> RaftMessage<T> {
+        RaftMessage::MembershipResp {
+            payload: Some(MembershipResponse {
+                success,
+                leader_id: self.current_leader.clone().unwrap_or_default(),
+                nodes: self.members(),
+                learners: self.learner_members()
+            }),
+            status: None
+        }
+    }
+
+    pub fn transfer_response(&self) -> RaftMessage<T> {
+        RaftMessage::TransferLeaderResp {
+            payload: Some(TransferLeadershipResponse {
+                success: false,
+                leader_id: self.current_leader.clone().unwrap_or_default()
+            }),
+            status: None
+        }
+    }
+
+    pub async fn status_response(&self) -> RaftMessage<T> {
+        let tracker = self.tracker.read().await;
+        let mut peers: Vec<PeerStatus> = self.nodes.iter().map(|node| (node, false))
+            .chain(self.learners.iter().map(|node| (node, true)))
+            .map(|(node, learner)| {
+                let state = self.nodes_state.get(&node.id);
+                PeerStatus {
+                    id: node.id.clone(),
+                    learner,
+                    match_index: state.map_or(0, |state| state.match_index),
+                    next_index: state.map_or(0, |state| state.next_index),
+                    connected: self.peers.is_connected(&node.id)
                 }
+            })
+            .collect();
+        peers.sort_by(|a, b| a.id.cmp(&b.id));
+        RaftMessage::StatusResp {
+            payload: StatusResponse {
+                id: self.id.clone(),
+                state: format!("{:?}", self.state),
+                term: self.current_term,
+                leader_id: self.current_leader.clone().unwrap_or_default(),
+                commit_index: self.commit_index,
+                last_applied: self.last_applied,
+                last_log_index: self.last_log_index,
+                last_log_term: self.last_log_term,
+                snapshot_no: self.snapshot_num,
+                last_snapshot_index: tracker.get_last_snapshot_index(),
+                last_snapshot_term: tracker.get_last_snapshot_term(),
+                peers
             }
         }
     }
 
+    pub async fn snapshot_response(&mut self) -> RaftMessage<T> {
+        if let Err(err) = self.take_snapshot().await {
+            error!(cause = %err, "Could not take the snapshot: ");
+            return RaftMessage::TakeSnapshotResp {
+                payload: None,
+                status: Some(tonic::Status::internal(err.to_string()))
+            };
+        }
+        let tracker = self.tracker.read().await;
+        RaftMessage::TakeSnapshotResp {
+            payload: Some(TakeSnapshotResponse {
+                snapshot_no: self.snapshot_num,
+                last_included_index: tracker.get_last_snapshot_index(),
+                last_included_term: tracker.get_last_snapshot_term()
+            }),
+            status: None
+        }
+    }
+
+    pub fn not_leader_error(&self) -> RaftMessage<T> {
+        match (&self.current_leader, &self.leader_client_addr) {
+            (Some(leader), Some((id, addr))) if *leader != self.id && leader == id =>
+                RaftMessage::ClientError { body: format!("NOTLEADER {}", addr) },
+            (Some(leader), _) if *leader != self.id =>
+                RaftMessage::ClientError { body: "NOTLEADER".into() },
+            _ => RaftMessage::ClientError { body: "RETRY leadership changed".into() }
+        }
+    }
+
+    // Writes accepted while leading stay pending after stepping down, until
+    // their index is either commited or overwritten by another leader.
+    pub fn park_writes(&mut self, writes: BTreeMap<u64, oneshot::Sender<RaftMessage<T>>>) {
+        self.pending_writes.extend(writes);
+    }
+
+    pub fn take_pending_writes(&mut self) -> BTreeMap<u64, oneshot::Sender<RaftMessage<T>>> {
+        std::mem::take(&mut self.pending_writes)
+    }
+
+    pub fn resolve_write(&mut self, index: u64, entry: LogEntry<T>) {
+        let tx = match self.pending_writes.remove(&index) {
+            Some(tx) => tx,
+            None => return
+        };
+        let response = match entry {
+            LogEntry::Normal(body) | LogEntry::Session{entity: body, ..} =>
+                RaftMessage::ClientResp { body },
+            LogEntry::Configuration{..} => self.membership_response(true),
+            LogEntry::Noop => self.not_leader_error()
+        };
+        let _ = tx.send(response);
+    }
+
+    pub fn fail_writes_from(&mut self, index: u64) {
+        for (_, tx) in self.pending_writes.split_off(&index) {
+            let _ = tx.send(self.not_leader_error());
+        }
+    }
+
+    pub fn is_member(&self) -> bool {
+        match &self.configuration {
+            Some(nodes) => nodes.contains(&self.id),
+            None => !self.learner
+        }
+    }
+
+    pub fn members(&self) -> Vec<NodeID> {
+        let mut members: Vec<NodeID> = self.nodes.iter().map(|node| node.id.clone()).collect();
+        if self.is_member() {
+            members.push(self.id.clone());
+        }
+        members.sort();
+        members
+    }
+
+    pub fn learner_members(&self) -> Vec<NodeID> {
+        let mut learners: Vec<NodeID> = self.learners.iter().map(|node| node.id.clone()).collect();
+        if self.learner {
+            learners.push(self.id.clone());
+        }
+        learners.sort();
+        learners
+    }
+
+    pub fn follower_state(&self) -> State {
+        if self.learner {
+            State::NonVoter
+        } else {
+            State::Follower
+        }
+    }
+
     pub fn set_state(&mut self, state: State) {
+        if state == State::Leader && self.state != State::Leader {
+            self.metrics.election_won();
+        }
+        self.metrics.set_state(&state);
         self.state = state;
     }
 
@@ -159,22 +478,36 @@ impl<T: ClientData, R: Tracker<Entity=T>> Raft<T, R> {
         self.nodes.clone()
     }
 
+    pub fn get_all_learners(&self) -> HashSet<Node> {
+        self.learners.clone()
+    }
+
     pub fn update_last_log(&mut self, index: u64, term: u64) {
         self.last_log_index = index;
-        self.last_log_term = term; 
+        self.last_log_term = term;
+        self.metrics.set_last_log_index(index);
+    }
+
+    // Called once the tracker has applied every entry up to index.
+    pub fn update_last_applied(&mut self, index: u64) {
+        self.last_applied = index;
+        self.metrics.set_last_applied(index);
     }
 
     pub fn update_commit_index(&mut self, index: u64, snappshot: bool) {
         self.commit_index = index;
+        self.metrics.set_commit_index(index);
         if index % self.snapshot_offset == 0 && snappshot {
             let _ = self.tx_snap.send(RaftMessage::SnapMsg);
         }
     }
 
     pub async fn take_snapshot(&mut self) -> crate::Result<()> {
+        let start = Instant::now();
         let mut tracker = self.tracker.write().await;
         tracker.take_snapshot().await?;
         self.snapshot_num += 1;
+        self.metrics.observe_snapshot("taken", start.elapsed());
         Ok(())
     }
 
@@ -5,12 +5,25 @@ use crate::raft_rpc::raft_rpc_client::RaftRpcClient;
 
 use crate::raft_rpc::{AppendEntriesRequest, AppendEntriesResponse};
 use crate::raft_rpc::{RequestVoteRequest, RequestVoteResponse};
+use crate::raft_rpc::{PreVoteRequest, PreVoteResponse};
+use crate::raft_rpc::{ReadIndexRequest, ReadIndexResponse};
 use crate::raft_rpc::{ForwardEntryRequest, ForwardEntryResponse};
 use crate::raft_rpc::{SnapshotRequest, SnapshotResponse};
+use crate::raft_rpc::{TimeoutNowRequest, TimeoutNowResponse};
 
-use crate::{Node, RaftMessage, ClientData};
+use crate::{Node, NodeID, RaftMessage, ClientData};
+use crate::parser::Session;
+use crate::tls::PeerTls;
+
+use tonic::Code;
+use tonic::transport::{Channel, Endpoint};
 
 use tokio::sync::{mpsc, oneshot};
+use tokio::time::{Duration, Instant};
+
+use std::collections::HashMap;
+use std::cmp::{min, max};
+use std::sync::{Arc, Mutex};
 
 use tracing::{info, error};
 
@@ -80,6 +93,59 @@ impl<T: ClientData> RaftRpc for RaftRpcService<T> {
         }
     }
 
+    async fn pre_vote(&self, request: Request<PreVoteRequest>) 
+        -> Result<Response<PreVoteResponse>, Status> {
+        let (tx, rx) = oneshot::channel();
+        let body = request.into_inner();
+        info!("{:?} Asked for pre vote", &body.candidate_id);
+        let resp = self.tx_rpc.send(RaftMessage::PreVoteMsg{
+            body,
+            tx
+        }); 
+        if let Err(err) = resp {
+            return Err(Status::internal(err.to_string()));
+        }
+        let resp = match rx.await {
+            Ok(msg) => msg,
+            Err(err) => return Err(Status::internal(err.to_string()))
+        };
+        match resp {
+            RaftMessage::PreVoteResp{payload, status} => {
+                if let Some(status) = status {
+                    return Err(status);
+                }
+                Ok(Response::new(payload))
+            },
+            _ => Err(Status::unknown("Unkown response recived"))
+        }
+    }
+
+    async fn read_index(&self, request: Request<ReadIndexRequest>) 
+        -> Result<Response<ReadIndexResponse>, Status> {
+        let (tx, rx) = oneshot::channel();
+        let resp = self.tx_rpc.send(RaftMessage::ReadIndexMsg{
+            body: request.into_inner(),
+            tx
+        }); 
+        if let Err(err) = resp {
+            return Err(Status::internal(err.to_string()));
+        }
+        let resp = match rx.await {
+            Ok(msg) => msg,
+            Err(err) => return Err(Status::internal(err.to_string()))
+        };
+        match resp {
+            RaftMessage::ReadIndexResp{payload, status} => {
+                if let Some(status) = status {
+                    return Err(status);
+                }
+                payload.map(Response::new).ok_or_else(|| Status::internal("Empty read index response"))
+            },
+            RaftMessage::ClientError{body} => Err(Status::unavailable(body)),
+            _ => Err(Status::unknown("Unkown response recived"))
+        }
+    }
+
     async fn forward_entry(&self, request: Request<ForwardEntryRequest>) ->
         Result<Response<ForwardEntryResponse>, Status> {
         let (tx, rx) = oneshot::channel();
@@ -94,8 +160,14 @@ impl<T: ClientData> RaftRpc for RaftRpcService<T> {
         };
 
         let msg = if req.iswrite {
+            let session = if req.client_id.is_empty() {
+                None
+            } else {
+                Some(Session { client_id: req.client_id, sequence: req.sequence })
+            };
             RaftMessage::ClientWriteMsg {
                 body: entity,
+                session,
                 tx
             }
         } else {
@@ -118,6 +190,7 @@ impl<T: ClientData> RaftRpc for RaftRpcService<T> {
                 let payload = serde_json::to_string(&body).unwrap();
                 return Ok(Response::new(ForwardEntryResponse { payload }));
             },
+            RaftMessage::ClientError{body} => {return Err(Status::unavailable(body));}
             _ => {return Err(Status::unknown("Unkown response recived"));}
         }
 
@@ -151,43 +224,159 @@ impl<T: ClientData> RaftRpc for RaftRpcService<T> {
         
     }
 
-}
+    async fn timeout_now(&self, request: Request<TimeoutNowRequest>) ->
+        Result<Response<TimeoutNowResponse>, Status> {
+        let (tx, rx) = oneshot::channel();
+        let body = request.into_inner();
+        info!("{:?} Asked to timeout now", &body.leader_id);
+        let resp = self.tx_rpc.send(RaftMessage::TimeoutNowMsg{
+            body,
+            tx
+        });
+        if let Err(err) = resp {
+            return Err(Status::internal(err.to_string()));
+        }
+        let resp = match rx.await {
+            Ok(msg) => msg,
+            Err(err) => return Err(Status::internal(err.to_string()))
+        };
+        match resp {
+            RaftMessage::TimeoutNowResp{payload, status} => {
+                if let Some(status) = status {
+                    return Err(status);
+                }
+                Ok(Response::new(payload))
+            },
+            _ => Err(Status::unknown("Unkown response recived"))
+        }
+    }
 
-pub async fn ask_for_vote(node: &Node, request: RequestVoteRequest) 
-    -> crate::Result<RequestVoteResponse> {
-    info!("Asking {} for vote", node.id);
-    let addr = format!("http://{}:{}", node.ip, node.port);
-    let mut client = RaftRpcClient::connect(addr).await?;
-    let response = client.request_vote(request).await?;
-    let resp = response.into_inner();
-    info!("Answerd with {:?}", resp);
-    Ok(resp)
 }
 
-pub async fn append_entries(node: &Node, request: AppendEntriesRequest) 
-    -> crate::Result<AppendEntriesResponse> {
-    let addr = format!("http://{}:{}", node.ip, node.port);
-    let mut client = RaftRpcClient::connect(addr).await?;
-    let response = client.append_entries(request).await?;
-    Ok(response.into_inner())
+// One multiplexed channel per peer, shared by every RPC sent to it. A peer
+// that could not be dialed is not tried again before its backoff passes.
+#[derive(Debug, Clone)]
+pub struct ChannelPool {
+    peers: Arc<Mutex<HashMap<NodeID, Peer>>>,
+    max_backoff: Duration,
+    tls: Option<PeerTls>
 }
 
-pub async fn forward(node: &Node, request: ForwardEntryRequest) 
-    -> crate::Result<ForwardEntryResponse> {
-    let addr = format!("http://{}:{}", node.ip, node.port);
-    info!("Forwarding entry to {}:{}", node.ip, node.port);
-    let mut client = RaftRpcClient::connect(addr).await?;
-    let response = client.forward_entry(request).await?;
-    Ok(response.into_inner())
+#[derive(Debug)]
+enum Peer {
+    Connected(Channel),
+    Failed {
+        backoff: Duration,
+        retry_at: Instant
+    }
 }
 
-pub async fn install_snapshot(node: &Node, request: SnapshotRequest) 
-    -> crate::Result<SnapshotResponse> {
-    info!("Sending snapshot to {}", node.id);
-    let addr = format!("http://{}:{}", node.ip, node.port);
-    let mut client = RaftRpcClient::connect(addr).await?;
-    let response = client.install_snapshot(request).await?;
-    let resp = response.into_inner();
-    info!("Answerd with {:?}", resp);
-    Ok(resp)
+impl ChannelPool {
+    pub fn new(max_backoff: Duration, tls: Option<PeerTls>) -> ChannelPool {
+        ChannelPool { peers: Arc::new(Mutex::new(HashMap::new())), max_backoff, tls }
+    }
+
+    pub fn remove(&self, id: &NodeID) {
+        self.peers.lock().unwrap().remove(id);
+    }
+
+    pub fn is_connected(&self, id: &NodeID) -> bool {
+        matches!(self.peers.lock().unwrap().get(id), Some(Peer::Connected(_)))
+    }
+
+    async fn client(&self, node: &Node) -> crate::Result<RaftRpcClient<Channel>> {
+        let backoff = match self.peers.lock().unwrap().get(&node.id) {
+            Some(Peer::Connected(channel)) => return Ok(RaftRpcClient::new(channel.clone())),
+            Some(Peer::Failed{retry_at, ..}) if *retry_at > Instant::now() =>
+                return Err(format!("Backing off from {}", node.id).into()),
+            Some(Peer::Failed{backoff, ..}) => *backoff,
+            None => Duration::from_millis(0)
+        };
+
+        let endpoint = match &self.tls {
+            Some(tls) => Endpoint::from_shared(format!("https://{}:{}", node.ip, node.port))?
+                .tls_config(tls.client_config(node.ip))?,
+            None => Endpoint::from_shared(format!("http://{}:{}", node.ip, node.port))?
+        };
+        match endpoint.connect().await {
+            Ok(channel) => {
+                info!("Connected to {}", node.id);
+                self.peers.lock().unwrap().insert(node.id.clone(), Peer::Connected(channel.clone()));
+                Ok(RaftRpcClient::new(channel))
+            },
+            Err(err) => {
+                let backoff = min(max(backoff * 2, Duration::from_millis(1)), self.max_backoff);
+                self.peers.lock().unwrap().insert(node.id.clone(),
+                    Peer::Failed { backoff, retry_at: Instant::now() + backoff });
+                Err(err.into())
+            }
+        }
+    }
+
+    // Transport failures surface as unknown, the peer gets dialed again on the
+    // next call instead of reusing a broken channel.
+    fn settle<M>(&self, node: &Node, response: Result<Response<M>, Status>) -> crate::Result<M> {
+        match response {
+            Ok(response) => Ok(response.into_inner()),
+            Err(status) => {
+                if status.code() == Code::Unknown {
+                    self.remove(&node.id);
+                }
+                Err(status.into())
+            }
+        }
+    }
+
+    pub async fn ask_for_vote(&self, node: &Node, request: RequestVoteRequest)
+        -> crate::Result<RequestVoteResponse> {
+        info!("Asking {} for vote", node.id);
+        let response = self.client(node).await?.request_vote(request).await;
+        let resp = self.settle(node, response)?;
+        info!("Answerd with {:?}", resp);
+        Ok(resp)
+    }
+
+    pub async fn ask_for_pre_vote(&self, node: &Node, request: PreVoteRequest)
+        -> crate::Result<PreVoteResponse> {
+        info!("Asking {} for pre vote", node.id);
+        let response = self.client(node).await?.pre_vote(request).await;
+        let resp = self.settle(node, response)?;
+        info!("Answerd with {:?}", resp);
+        Ok(resp)
+    }
+
+    pub async fn append_entries(&self, node: &Node, request: AppendEntriesRequest)
+        -> crate::Result<AppendEntriesResponse> {
+        let response = self.client(node).await?.append_entries(request).await;
+        self.settle(node, response)
+    }
+
+    pub async fn forward(&self, node: &Node, request: ForwardEntryRequest)
+        -> crate::Result<ForwardEntryResponse> {
+        info!("Forwarding entry to {}:{}", node.ip, node.port);
+        let response = self.client(node).await?.forward_entry(request).await;
+        self.settle(node, response)
+    }
+
+    pub async fn read_index(&self, node: &Node, request: ReadIndexRequest)
+        -> crate::Result<ReadIndexResponse> {
+        let response = self.client(node).await?.read_index(request).await;
+        self.settle(node, response)
+    }
+
+    pub async fn install_snapshot(&self, node: &Node, request: SnapshotRequest)
+        -> crate::Result<SnapshotResponse> {
+        info!("Sending snapshot to {}", node.id);
+        let response = self.client(node).await?.install_snapshot(request).await;
+        let resp = self.settle(node, response)?;
+        info!("Answerd with {:?}", resp);
+        Ok(resp)
+    }
+
+    pub async fn timeout_now(&self, node: &Node, request: TimeoutNowRequest)
+        -> crate::Result<TimeoutNowResponse> {
+        info!("Asking {} to timeout now", node.id);
+        let response = self.client(node).await?.timeout_now(request).await;
+        self.settle(node, response)
+    }
 }
@@ -1,23 +1,33 @@
 use tokio::net::{TcpListener, TcpStream};
-use tokio::io::{AsyncReadExt, AsyncWriteExt, BufWriter};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncReadExt, AsyncWriteExt, BufWriter};
 use tokio::time::{self, Duration};
 
 use std::future::Future;
+use std::collections::HashMap;
+use std::net::SocketAddr;
 
 use tokio::sync::{mpsc, oneshot, RwLock};
 
-use tonic::transport::Server;
+use tonic::transport::{Server, ServerTlsConfig};
 
 use tracing::{info, error};
 
 use crate::{Raft, ConfigMap, RaftMessage, ClientData, Tracker};
 
 use crate::rpc::RaftRpcService;
+use crate::tls::{self, PeerTls};
+use crate::metrics::{self, Metrics};
 use crate::raft_rpc::raft_rpc_server::RaftRpcServer;
+use crate::raft_rpc::admin_server::AdminServer;
+use crate::admin::{AdminService, AdminAuth};
 
 use crate::parser::{Parser, Kind};
 
-use bytes::BytesMut;
+use bytes::{Bytes, BytesMut};
+
+use tokio_rustls::TlsAcceptor;
+
+use subtle::ConstantTimeEq;
 
 use std::marker::PhantomData;
 
@@ -26,38 +36,99 @@ use std::sync::Arc;
 pub struct Listener<P: Parser<T>, T: ClientData> {
     listener: TcpListener,
     tx_client: mpsc::UnboundedSender<RaftMessage<T>>,
+    timeout: Duration,
+    tls: Option<TlsAcceptor>,
+    users: Option<Arc<HashMap<String, String>>>,
+    metrics: Option<Metrics>,
     parser: PhantomData<P>
 }
 
 pub struct Handler<P: Parser<T>, T: ClientData> {
-    stream: BufWriter<TcpStream>,
+    stream: BufWriter<Box<dyn ClientStream>>,
     tx_client: mpsc::UnboundedSender<RaftMessage<T>>,
     buffer: BytesMut,
     parser: P,
+    default_timeout: Duration,
+    timeout: Duration,
+    users: Option<Arc<HashMap<String, String>>>,
+    authenticated: bool,
+    metrics: Option<Metrics>,
     data: PhantomData<T>
 }
 
+trait ClientStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> ClientStream for S {}
+
 
 pub async fn run<T: ClientData, P: Parser<T>, R: Tracker<Entity=T>>(shutdown: impl Future,
-    config: ConfigMap, parser: P, tracker: R) -> crate::Result<()> {
+    config: ConfigMap, parser: P, mut tracker: R) -> crate::Result<()> {
+    tracker.recover().await?;
+
     let addr = format!("{}:{}", config.host, config.port).parse()?;
     let tcp_listener = TcpListener::bind(&format!("{}:{}",
             config.connecntion_host, config.connecntion_port)).await?;
 
     let (tx_rpc, rx_rpc) = mpsc::unbounded_channel();
 
-    let raft_rpc = RaftRpcService::<T>::new(tx_rpc.clone());
+    let mut listener = Listener::new(tcp_listener, tx_rpc.clone(),
+        Duration::from_millis(config.client_timeout));
+    let admin_tls = match (&config.client_tls_cert, &config.client_tls_key) {
+        (Some(cert), Some(key)) => {
+            listener.set_tls(tls::client_acceptor(cert, key)?);
+            Some(tls::admin_config(cert, key)?)
+        },
+        (None, None) => None,
+        _ => return Err("client_tls_cert and client_tls_key must be set together".into())
+    };
+    if !config.users.is_empty() {
+        listener.set_users(config.users.clone());
+    }
 
-    let mut listener = Listener {
-        listener: tcp_listener,
-        tx_client: tx_rpc.clone(),
-        parser: PhantomData
+    let metrics_addr: Option<SocketAddr> = match &config.metrics_addr {
+        Some(addr) => Some(addr.parse()?),
+        None => None
     };
 
-    let svc = RaftRpcServer::new(raft_rpc);
+    let admin: Option<(SocketAddr, String)> = match (&config.admin_addr, &config.admin_token) {
+        (Some(addr), Some(token)) if !token.is_empty() => Some((addr.parse()?, token.clone())),
+        (Some(_), _) => return Err("admin_addr needs an admin_token".into()),
+        (None, _) => None
+    };
+
+    let id = format!("{}:{}", config.host, config.port);
+    let mut raft = Raft::new(config, rx_rpc, Arc::new(RwLock::new(tracker)), id)?;
+    listener.set_metrics(raft.metrics.clone());
+
+    if let Some(addr) = metrics_addr {
+        let metrics = raft.metrics.clone();
+        tokio::spawn(async move {
+                if let Err(err) = metrics::serve(addr, metrics).await {
+                    error!(cause = %err, "Metrics server stopped: ");
+                }
+            }
+        );
+    }
 
+    if let Some(tls) = raft.tls.clone() {
+        tokio::spawn(tls.watch());
+    }
+
+    if let Some((addr, token)) = admin {
+        let tx_rpc = tx_rpc.clone();
+        tokio::spawn(async move {
+                if let Err(err) = serve_admin::<T>(tx_rpc, addr, token, admin_tls).await {
+                    error!(cause = %err, "Admin server stopped: ");
+                }
+            }
+        );
+    }
+
+    let tls = raft.tls.clone();
     tokio::spawn(async move {
-            let _ = Server::builder().add_service(svc).serve(addr).await;
+            if let Err(err) = serve_rpc::<T>(tx_rpc, addr, tls).await {
+                error!(cause = %err, "Raft rpc server stopped: ");
+            }
         }
     );
 
@@ -65,8 +136,6 @@ pub async fn run<T: ClientData, P: Parser<T>, R: Tracker<Entity=T>>(shutdown: im
             let _ = listener.run(parser).await;
         }
     );
-    let id = format!("{}:{}", config.host, config.port);
-    let mut raft = Raft::new(config, rx_rpc, Arc::new(RwLock::new(tracker)), id);
     tokio::select! {
         res = raft.run() => {
             if let Err(err) = res {
@@ -80,29 +149,92 @@ pub async fn run<T: ClientData, P: Parser<T>, R: Tracker<Entity=T>>(shutdown: im
     Ok(())
 }
 
+pub async fn serve_rpc<T: ClientData>(tx_rpc: mpsc::UnboundedSender<RaftMessage<T>>,
+    addr: SocketAddr, tls: Option<PeerTls>) -> crate::Result<()> {
+    let svc = RaftRpcServer::new(RaftRpcService::<T>::new(tx_rpc));
+    let mut server = Server::builder();
+    if let Some(tls) = tls {
+        server = server.tls_config(tls.server_config())?;
+    }
+    server.add_service(svc).serve(addr).await?;
+    Ok(())
+}
+
+pub async fn serve_admin<T: ClientData>(tx_rpc: mpsc::UnboundedSender<RaftMessage<T>>,
+    addr: SocketAddr, token: String, tls: Option<ServerTlsConfig>) -> crate::Result<()> {
+    let admin = AdminServer::with_interceptor(AdminService::<T>::new(tx_rpc), AdminAuth::new(token));
+    let mut server = Server::builder();
+    if let Some(tls) = tls {
+        server = server.tls_config(tls)?;
+    }
+    server.add_service(admin).serve(addr).await?;
+    Ok(())
+}
+
+// Takes as long for a wrong password as for a right one of the same length.
+fn password_matches(expected: Option<&String>, password: &str) -> bool {
+    match expected {
+        Some(expected) => expected.as_bytes().ct_eq(password.as_bytes()).into(),
+        None => false
+    }
+}
+
 impl<P: Parser<T>, T: ClientData> Listener<P, T> {
-    pub fn new(listener: TcpListener, tx_client: mpsc::UnboundedSender<RaftMessage<T>>) 
-        -> Listener<P, T> {
+    pub fn new(listener: TcpListener, tx_client: mpsc::UnboundedSender<RaftMessage<T>>,
+        timeout: Duration) -> Listener<P, T> {
         Listener {
             listener,
             tx_client,
+            timeout,
+            tls: None,
+            users: None,
+            metrics: None,
             parser: PhantomData
         }
     }
 
+    pub fn set_tls(&mut self, acceptor: TlsAcceptor) {
+        self.tls = Some(acceptor);
+    }
+
+    // Once users are set every connection has to AUTH before its requests
+    // are passed on.
+    pub fn set_users(&mut self, users: HashMap<String, String>) {
+        self.users = Some(Arc::new(users));
+    }
+
+    pub fn set_metrics(&mut self, metrics: Metrics) {
+        self.metrics = Some(metrics);
+    }
+
     pub async fn run(&mut self, parser: P) -> crate::Result<()> {
         loop {
             let socket = self.accept().await?;
 
+            let tls = self.tls.clone();
             let mut handler = Handler {
-                stream: BufWriter::new(socket),
+                stream: BufWriter::new(Box::new(socket) as Box<dyn ClientStream>),
                 buffer: BytesMut::with_capacity(4096),
                 tx_client: self.tx_client.clone(),
                 parser: parser.clone(),
+                default_timeout: self.timeout,
+                timeout: self.timeout,
+                users: self.users.clone(),
+                authenticated: self.users.is_none(),
+                metrics: self.metrics.clone(),
                 data: PhantomData
             };
 
             tokio::spawn(async move {
+                    if let Some(tls) = tls {
+                        match tls.accept(handler.stream.into_inner()).await {
+                            Ok(stream) => handler.stream = BufWriter::new(Box::new(stream)),
+                            Err(err) => {
+                                info!("TLS handshake failed: {}", err);
+                                return;
+                            }
+                        }
+                    }
                     let _ = handler.run().await;
                 }
             );
@@ -136,29 +268,62 @@ impl<P: Parser<T>, T: ClientData> Handler<P, T> {
     pub async fn run(&mut self) -> crate::Result<()> {
         loop {
             if let Some(frame) = self.parser.parse(&mut self.buffer)? {
+                if !self.authenticated && !matches!(frame, Kind::Auth(..) | Kind::Invalid(_)) {
+                    let buf = self.parser.into_error("NOAUTH Authentication required")?;
+                    self.write_response(&buf).await?;
+                    continue;
+                }
                 let (tx, rx) = oneshot::channel();
-                let msg = match frame {
-                    Kind::Read(frame) => RaftMessage::ClientReadMsg { body: frame, tx },
-                    Kind::Write(frame) => RaftMessage::ClientWriteMsg { body: frame, tx }
+                let start = time::Instant::now();
+                let (kind, msg) = match frame {
+                    Kind::Read(frame) => ("read", RaftMessage::ClientReadMsg { body: frame, tx }),
+                    Kind::Write(frame, session) => ("write", RaftMessage::ClientWriteMsg { body: frame, session, tx }),
+                    Kind::Timeout(timeout) => {
+                        self.timeout = match timeout {
+                            0 => self.default_timeout,
+                            timeout => Duration::from_millis(timeout)
+                        };
+                        let buf = self.parser.into_ok()?;
+                        self.write_response(&buf).await?;
+                        continue;
+                    }
+                    Kind::Auth(user, password) => {
+                        let buf = match &self.users {
+                            Some(users) if password_matches(users.get(&user), &password) => {
+                                self.authenticated = true;
+                                self.parser.into_ok()?
+                            },
+                            Some(_) => self.parser.into_error("WRONGPASS invalid username-password pair")?,
+                            None => self.parser.into_error("ERR no users are configured")?
+                        };
+                        self.write_response(&buf).await?;
+                        continue;
+                    }
+                    Kind::Invalid(err) => {
+                        let buf = self.parser.into_error(&err)?;
+                        self.write_response(&buf).await?;
+                        continue;
+                    }
                 };
                 self.tx_client.send(msg)?;
 
-                let resp = rx.await?;
-
-                info!("Sending response back to client {:?}", resp);
-
-                match resp {
-                    RaftMessage::ClientResp{body} =>  {
-                        let buf = self.parser.unparse(body)?;
-                        self.stream.write_all(&buf).await?;
-                        self.stream.flush().await?;
+                // Dropping the receiver on timeout tells the raft side to stop waiting too.
+                let buf = match time::timeout(self.timeout, rx).await {
+                    Ok(resp) => {
+                        let resp = resp?;
+                        info!("Sending response back to client {:?}", resp);
+                        match resp {
+                            RaftMessage::ClientResp{body} => self.parser.unparse(body)?,
+                            RaftMessage::ClientError { body } => self.parser.into_error(&body)?,
+                            RaftMessage::ClientRedirect { leader } => self.parser.into_redirect(&leader)?,
+                            _ => {return Err("Unkown response recived".into());}
+                        }
                     },
-                    RaftMessage::ClientError { body } => {
-                        let buf = self.parser.into_error(&body)?;
-                        self.stream.write_all(&buf).await?;
-                        self.stream.flush().await?;
-                    }
-                    _ => {return Err("Unkown response recived".into());}
+                    Err(_) => self.parser.into_error("TIMEOUT request timed out")?
+                };
+                self.write_response(&buf).await?;
+                if let Some(metrics) = &self.metrics {
+                    metrics.observe_request(kind, start.elapsed());
                 }
             }
 
@@ -171,4 +336,10 @@ impl<P: Parser<T>, T: ClientData> Handler<P, T> {
             }
         }
     }
+
+    async fn write_response(&mut self, buf: &Bytes) -> crate::Result<()> {
+        self.stream.write_all(buf).await?;
+        self.stream.flush().await?;
+        Ok(())
+    }
 }
@@ -3,9 +3,9 @@ use crate::raft::State;
 use tracing::{error, info};
 use tokio::time::sleep_until;
 use tokio::sync::mpsc;
-use crate::rpc::ask_for_vote;
 
 use crate::raft_rpc::{RequestVoteRequest, RequestVoteResponse};
+use crate::raft_rpc::{PreVoteRequest, PreVoteResponse};
 
 #[derive(Debug)]
 pub struct Candidate <'a, T: ClientData, R: Tracker<Entity=T>> {
@@ -25,9 +25,16 @@ impl<'a, T: ClientData, R: Tracker<Entity=T>> Candidate<'a, T, R> {
         info!("Running at Candidate State");
         info!("Current term is {}.", self.raft.current_term);
         while self.is_candidate() {
-            self.raft.current_term += 1;
+            // A leadership transfer skips the pre vote, the followers
+            // would reject it while they still hear from the old leader.
+            if !std::mem::take(&mut self.raft.timeout_now) && !self.pre_vote().await? {
+                continue;
+            }
 
-            self.raft.voted_for = Some(self.raft.id.clone());
+            let term = self.raft.current_term + 1;
+            let id = self.raft.id.clone();
+            self.raft.update_hard_state(term, Some(id))?;
+            self.raft.metrics.election_started();
             self.number_of_votes = 1;
 
             let mut vote_rx = self.ask_for_votes();
@@ -38,7 +45,7 @@ impl<'a, T: ClientData, R: Tracker<Entity=T>> Candidate<'a, T, R> {
                 tokio::select! {
                     _ = election_timeout => break,
                     Some(response) = vote_rx.recv() => self.handle_vote(response)?,
-                    Some(request)  = self.raft.rx_rpc.recv() => self.handle_api_request(request),
+                    Some(request)  = self.raft.rx_rpc.recv() => self.handle_api_request(request).await,
                 }
             }
         }
@@ -46,48 +53,98 @@ impl<'a, T: ClientData, R: Tracker<Entity=T>> Candidate<'a, T, R> {
         Ok(())
     }
 
-    fn handle_api_request(&mut self, request: RaftMessage<T>) {
+    async fn pre_vote(&mut self) -> crate::Result<bool> {
+        info!("Running pre vote for term {}.", self.raft.current_term + 1);
+        self.number_of_votes = 1;
+        if self.has_enough_vote() {
+            return Ok(true);
+        }
+
+        let mut vote_rx = self.ask_for_pre_votes();
+
+        let election_timeout = sleep_until(self.raft.generate_timeout());
+        tokio::pin!(election_timeout);
+
+        while self.is_candidate() {
+            tokio::select! {
+                _ = &mut election_timeout => return Ok(false),
+                Some(response) = vote_rx.recv() => {
+                    if self.handle_pre_vote(response)? {
+                        return Ok(true);
+                    }
+                },
+                Some(request)  = self.raft.rx_rpc.recv() => self.handle_api_request(request).await,
+            }
+        }
+
+        Ok(false)
+    }
+
+    async fn handle_api_request(&mut self, request: RaftMessage<T>) {
         match request {
             RaftMessage::VoteMsg{tx, body} => {
-                let resp = self.handle_vote_request(body);
+                let resp = self.raft.handle_vote_request(body);
+                let _ = tx.send(resp);
+            }
+            RaftMessage::PreVoteMsg{tx, body} => {
+                let resp = self.raft.handle_pre_vote_request(body);
                 let _ = tx.send(resp);
             }
-            RaftMessage::AppendMsg{tx, ..} => {
+            RaftMessage::AppendMsg{tx, body} => {
+                if body.term >= self.raft.current_term {
+                    self.raft.set_state(State::Follower);
+                }
                 let resp = RaftMessage::AppendResp {
                     status: Some(tonic::Status::cancelled("Node is in Candidate state")),
                     payload: None 
                 };
                 let _ = tx.send(resp);
             },
+            RaftMessage::InstallSnapshot{tx, body} => {
+                if body.term >= self.raft.current_term {
+                    self.raft.set_state(State::Follower);
+                }
+                let resp = RaftMessage::InstallSnapshotResp {
+                    status: Some(tonic::Status::cancelled("Node is in Candidate state")),
+                    payload: crate::raft_rpc::SnapshotResponse {
+                        term: self.raft.current_term,
+                        offset: 0,
+                        done: false
+                    }
+                };
+                let _ = tx.send(resp);
+            },
+            RaftMessage::ReadIndexMsg{tx, ..} => {
+                let _ = tx.send(RaftMessage::ReadIndexResp {
+                    payload: None,
+                    status: Some(tonic::Status::failed_precondition("Node is in Candidate state"))
+                });
+            },
+            RaftMessage::ClientReadMsg{tx, ..} | RaftMessage::ClientWriteMsg{tx, ..} => {
+                let _ = tx.send(RaftMessage::ClientError{ body: "No leader exist".into() });
+            },
+            RaftMessage::AddServerMsg{tx, ..} | RaftMessage::RemoveServerMsg{tx, ..} => {
+                let _ = tx.send(self.raft.membership_response(false));
+            },
+            RaftMessage::TransferLeaderMsg{tx, ..} => {
+                let _ = tx.send(self.raft.transfer_response());
+            },
+            RaftMessage::TimeoutNowMsg{tx, ..} => {
+                let _ = tx.send(RaftMessage::TimeoutNowResp {
+                    payload: crate::raft_rpc::TimeoutNowResponse { term: self.raft.current_term },
+                    status: Some(tonic::Status::failed_precondition("Node is in Candidate state"))
+                });
+            },
+            RaftMessage::StatusMsg{tx} => {
+                let _ = tx.send(self.raft.status_response().await);
+            },
+            RaftMessage::TakeSnapshotMsg{tx} => {
+                let _ = tx.send(self.raft.snapshot_response().await);
+            },
             _ => unreachable!(),
         }
     }
 
-    fn handle_vote_request(&mut self, body: RequestVoteRequest) -> RaftMessage<T> {
-        if body.term > self.raft.current_term {
-            self.raft.current_term = body.term;
-        }
-        if body.last_log_index >= self.raft.last_index() && 
-            body.last_log_term >= self.raft.last_term() {
-            self.raft.set_state(State::Follower);
-            self.raft.voted_for = Some(body.candidate_id);
-            return RaftMessage::VoteResp {
-                status: None,
-                payload: RequestVoteResponse {
-                    term: self.raft.current_term,
-                    vote_granted: true
-                }
-            };
-        }
-        return RaftMessage::VoteResp {
-            status: None,
-            payload: RequestVoteResponse {
-                term: self.raft.current_term,
-                vote_granted: false
-            }
-        };
-    }
-
     pub fn ask_for_votes(&self) -> mpsc::Receiver<RequestVoteResponse> {
         let nodes = self.raft.get_all_nodes();
 
@@ -97,6 +154,7 @@ impl<'a, T: ClientData, R: Tracker<Entity=T>> Candidate<'a, T, R> {
 
         for node in nodes.into_iter() {
             let res_tx = tx.clone();
+            let peers = self.raft.peers.clone();
             let request = RequestVoteRequest {
                 term: self.raft.current_term,
                 candidate_id: self.raft.id.to_string(),
@@ -105,7 +163,7 @@ impl<'a, T: ClientData, R: Tracker<Entity=T>> Candidate<'a, T, R> {
             };
             let _ = tokio::spawn(
                 async move {
-                    match ask_for_vote(&node, request).await {
+                    match peers.ask_for_vote(&node, request).await {
                         Ok(response) =>  {
                             let _ = res_tx.send(response).await;
                         },
@@ -118,12 +176,56 @@ impl<'a, T: ClientData, R: Tracker<Entity=T>> Candidate<'a, T, R> {
         return rx;
     }
 
+    pub fn ask_for_pre_votes(&self) -> mpsc::Receiver<PreVoteResponse> {
+        let nodes = self.raft.get_all_nodes();
+
+        let (tx, rx) = mpsc::channel(nodes.len());
+
+        for node in nodes.into_iter() {
+            let res_tx = tx.clone();
+            let peers = self.raft.peers.clone();
+            let request = PreVoteRequest {
+                term: self.raft.current_term + 1,
+                candidate_id: self.raft.id.to_string(),
+                last_log_index: self.raft.last_index(),
+                last_log_term: self.raft.last_term()
+            };
+            tokio::spawn(
+                async move {
+                    match peers.ask_for_pre_vote(&node, request).await {
+                        Ok(response) =>  {
+                            let _ = res_tx.send(response).await;
+                        },
+                        Err(e) => error!(err=%e,"Error in comunicating with {:?}", node)
+                    }
+                }
+            );
+        }
+
+        rx
+    }
+
+    fn handle_pre_vote(&mut self, response: PreVoteResponse) -> crate::Result<bool> {
+        if response.term > self.raft.current_term {
+            self.raft.update_hard_state(response.term, None)?;
+            self.raft.set_state(State::Follower);
+            self.raft.current_leader = None;
+            return Ok(false);
+        }
+
+        if response.vote_granted {
+            self.number_of_votes += 1;
+            info!("A pre vote granted no {}", self.number_of_votes);
+        }
+
+        Ok(self.has_enough_vote())
+    }
+
     fn handle_vote(&mut self, response: RequestVoteResponse) -> crate::Result<()> {
         if response.term > self.raft.current_term {
+            self.raft.update_hard_state(response.term, None)?;
             self.raft.set_state(State::Follower);
-            self.raft.current_term = response.term;
             self.raft.current_leader = None;
-            self.raft.voted_for = None;
             return Ok(());
         }
 
@@ -139,7 +241,7 @@ impl<'a, T: ClientData, R: Tracker<Entity=T>> Candidate<'a, T, R> {
     }
 
     fn has_enough_vote(&self) -> bool {
-        self.number_of_votes > (self.raft.nodes.len() as u32) / 2 
+        2 * self.number_of_votes > self.raft.nodes.len() as u32 + 1
     }
 
     fn is_candidate(&self) -> bool {
@@ -1,25 +1,28 @@
-use crate::{Raft, ClientData, Tracker, RaftMessage};
+use crate::{Raft, ClientData, Tracker, RaftMessage, Node};
+use crate::tracker::LogEntry;
+use crate::parser::Session;
 use crate::raft::State;
 use tracing::{instrument, info, error};
-use tokio::time::sleep_until;
+use tokio::time::{sleep_until, Instant};
 use crate::raft_rpc::{AppendEntriesResponse, AppendEntriesRequest, SnapshotRequest, SnapshotResponse};
-use crate::raft_rpc::ForwardEntryRequest;
-use crate::rpc::forward;
+use crate::raft_rpc::{ForwardEntryRequest, ReadIndexRequest};
+use crate::raft_rpc::{TimeoutNowRequest, TimeoutNowResponse};
 use tokio::sync::oneshot::Sender;
 
 #[derive(Debug)]
 pub struct Follower <'a, T: ClientData, R: Tracker<Entity=T>> {
-    raft: &'a mut Raft<T, R>
+    raft: &'a mut Raft<T, R>,
+    pending_reads: Vec<(u64, T, Sender<RaftMessage<T>>)>
 }
 
 impl<'a, T: ClientData, R: Tracker<Entity=T>> Follower<'a, T, R> {
     pub fn new(raft: &'a mut Raft<T, R>) -> Follower<T, R> {
-        Follower { raft }
+        Follower { raft, pending_reads: Vec::new() }
     }
 
     #[instrument(level="info", skip(self))]
     pub async fn run(&mut self) -> crate::Result<()> {
-        info!("Running at Follower State");
+        info!("Running at {:?} State", self.raft.state);
         info!("Current term is {}.", self.raft.current_term);
         while self.is_follower() {
             let election_timeout = sleep_until(self.raft.generate_timeout());
@@ -27,7 +30,9 @@ impl<'a, T: ClientData, R: Tracker<Entity=T>> Follower<'a, T, R> {
             tokio::select! {
                 _ = election_timeout => {
                     info!("Timed out");
-                    self.raft.set_state(State::Candidate)
+                    if self.raft.state == State::Follower && self.raft.is_member() {
+                        self.raft.set_state(State::Candidate)
+                    }
                 },
                 Some(request)  = self.raft.rx_rpc.recv() => {
                     match self.handle_api_request(request).await {
@@ -37,6 +42,10 @@ impl<'a, T: ClientData, R: Tracker<Entity=T>> Follower<'a, T, R> {
                 },
                 Some(_) = self.raft.rx_snap.recv() => {
                     self.raft.take_snapshot().await?
+                },
+                Some(RaftMessage::ReadReady{read_index, body, tx}) = self.raft.rx_read.recv() => {
+                    self.pending_reads.push((read_index, body, tx));
+                    self.serve_reads().await;
                 }
             }
         }
@@ -45,7 +54,7 @@ impl<'a, T: ClientData, R: Tracker<Entity=T>> Follower<'a, T, R> {
     }
 
     fn is_follower(&self) -> bool {
-        self.raft.state == State::Follower
+        matches!(self.raft.state, State::Follower | State::NonVoter)
     }
 
     async fn handle_api_request(&mut self, request: RaftMessage<T>) -> crate::Result<()> {
@@ -54,60 +63,215 @@ impl<'a, T: ClientData, R: Tracker<Entity=T>> Follower<'a, T, R> {
                 info!("Recived a vote msg from {}", body.candidate_id);
                 let _ = tx.send(self.raft.handle_vote_request(body));
             },
+            RaftMessage::PreVoteMsg{body, tx} => {
+                info!("Recived a pre vote msg from {}", body.candidate_id);
+                let _ = tx.send(self.raft.handle_pre_vote_request(body));
+            },
             RaftMessage::AppendMsg{body, tx} => {
                 let _ = tx.send(self.handle_append_entry(body).await);
+                self.serve_reads().await;
+            },
+            RaftMessage::ClientReadMsg{body, tx} if self.raft.follower_read_index => {
+                self.request_read_index(body, tx);
+            },
+            RaftMessage::ClientReadMsg{tx, ..} | RaftMessage::ClientWriteMsg{tx, ..} if self.raft.redirect => {
+                let _ = tx.send(self.redirect_response());
             },
             RaftMessage::ClientReadMsg{body, tx} => {
-                let _ = self.forward_client_request(body, tx, false);
+                let _ = self.forward_client_request(body, None, tx, false);
+            },
+            RaftMessage::ReadIndexMsg{tx, ..} => {
+                let _ = tx.send(RaftMessage::ReadIndexResp {
+                    payload: None,
+                    status: Some(tonic::Status::failed_precondition("Node is not the leader"))
+                });
             },
-            RaftMessage::ClientWriteMsg{body, tx} => {
-                let _ = self.forward_client_request(body, tx, true);
+            RaftMessage::ClientWriteMsg{body, session, tx} => {
+                let _ = self.forward_client_request(body, session, tx, true);
             },
             RaftMessage::InstallSnapshot{body, tx} => {
                 let _ = tx.send(self.handle_snappshot(body).await);
+                self.serve_reads().await;
+            },
+            RaftMessage::AddServerMsg{tx, ..} | RaftMessage::RemoveServerMsg{tx, ..} => {
+                let _ = tx.send(self.raft.membership_response(false));
+            },
+            RaftMessage::TransferLeaderMsg{tx, ..} => {
+                let _ = tx.send(self.raft.transfer_response());
+            },
+            RaftMessage::TimeoutNowMsg{body, tx} => {
+                let _ = tx.send(self.handle_timeout_now(body));
+            },
+            RaftMessage::StatusMsg{tx} => {
+                let _ = tx.send(self.raft.status_response().await);
+            },
+            RaftMessage::TakeSnapshotMsg{tx} => {
+                let _ = tx.send(self.raft.snapshot_response().await);
             },
             _ => unreachable!()
         }
         Ok(())
     }
 
-    #[instrument(level="info", skip(self))]
+    #[instrument(level="info", skip(self, body))]
     async fn handle_snappshot(&mut self, body: SnapshotRequest) -> RaftMessage<T> {
-        let payload = SnapshotResponse { term: self.raft.current_term }; 
+        let mut payload = SnapshotResponse { term: self.raft.current_term, offset: 0, done: false };
         if body.term < self.raft.current_term {
             return RaftMessage::InstallSnapshotResp { payload, status: None};
         }
-
-        let entity = serde_json::from_str(&body.data).unwrap();
+        self.raft.heard_from_leader();
+        self.raft.current_leader = Some(body.leader_id.clone());
 
         let mut tracker = self.raft.tracker.write().await;
 
-        match tracker.load_snapshot(&entity, body.last_included_term,
-            body.last_included_index, body.offset).await {
-            Ok(_) => info!("Snapshot loaded"),
-            Err(err) => error!("Could not load snapshot cause {}", err)
+        // Already covered by our own commited log, nothing to install.
+        if body.last_included_index <= tracker.get_last_commited_index() {
+            payload.done = true;
+            return RaftMessage::InstallSnapshotResp { payload, status: None };
+        }
+
+        payload.offset = match tracker.stage_snapshot(body.last_included_term,
+            body.last_included_index, body.offset, &body.data) {
+            Ok(offset) => offset,
+            Err(err) => {
+                error!(cause = %err, "Could not stage the snapshot: ");
+                return RaftMessage::InstallSnapshotResp {
+                    payload,
+                    status: Some(tonic::Status::internal("Could not stage the snapshot"))
+                };
+            }
+        };
+        if !body.done || payload.offset != body.offset + body.data.len() as u64 {
+            return RaftMessage::InstallSnapshotResp { payload, status: None };
+        }
+
+        let start = Instant::now();
+        match tracker.load_snapshot(body.last_included_term,
+            body.last_included_index, body.snapshot_no).await {
+            Ok(_) => {
+                info!("Snapshot loaded");
+                self.raft.metrics.observe_snapshot("installed", start.elapsed());
+            },
+            Err(err) => {
+                error!("Could not load snapshot cause {}", err);
+                return RaftMessage::InstallSnapshotResp {
+                    payload,
+                    status: Some(tonic::Status::internal("Could not load the snapshot"))
+                };
+            }
         }
         let commit_index = tracker.get_last_commited_index();
         drop(tracker);
 
         self.raft.update_last_log(body.last_included_index, body.last_included_term);
+        self.raft.update_last_applied(commit_index);
         self.raft.update_commit_index(commit_index, false);
-        self.raft.snapshot_num =  body.offset;
+        self.raft.snapshot_num = body.snapshot_no;
+        // The snapshot replaced our whole log, so it's unknown which writes survived.
+        self.raft.fail_writes_from(0);
+        if !body.nodes.is_empty() {
+            if let Err(err) = self.raft.apply_configuration(body.nodes, body.learners) {
+                error!(cause = %err, "Could not apply the configuration: ");
+            }
+        }
 
+        payload.done = true;
         return RaftMessage::InstallSnapshotResp { payload, status: None };
     }
 
+    fn handle_timeout_now(&mut self, body: TimeoutNowRequest) -> RaftMessage<T> {
+        let payload = TimeoutNowResponse { term: self.raft.current_term };
+        if body.term != self.raft.current_term || self.raft.state != State::Follower ||
+            !self.raft.is_member() {
+            return RaftMessage::TimeoutNowResp {
+                payload,
+                status: Some(tonic::Status::failed_precondition("Could not start an election"))
+            };
+        }
+        info!("{} asked to start an election", body.leader_id);
+        self.raft.timeout_now = true;
+        self.raft.set_state(State::Candidate);
+        RaftMessage::TimeoutNowResp { payload, status: None }
+    }
+
+    fn leader_node(&self) -> Option<Node> {
+        let id = self.raft.current_leader.as_ref()?;
+        self.raft.get_all_nodes().into_iter().find(|x| &x.id == id)
+    }
+
+    // Smart clients can talk to the leader directly instead of going through
+    // the forwarding hop.
+    fn redirect_response(&self) -> RaftMessage<T> {
+        match (&self.raft.current_leader, &self.raft.leader_client_addr) {
+            (Some(leader), Some((id, addr))) if leader == id =>
+                RaftMessage::ClientRedirect { leader: addr.clone() },
+            _ => RaftMessage::ClientError{ body: "No leader exist".into() }
+        }
+    }
+
+    #[instrument(level="info", skip(self))]
+    fn request_read_index(&self, body: T, tx: Sender<RaftMessage<T>>) {
+        let node = match self.leader_node() {
+            Some(node) => node,
+            None => {
+                let _ = tx.send(RaftMessage::ClientError{ body: "No leader exist".into() });
+                return;
+            }
+        };
+        let request = ReadIndexRequest { node_id: self.raft.id.clone() };
+        let tx_read = self.raft.tx_read.clone();
+        let peers = self.raft.peers.clone();
+        tokio::spawn(async move {
+            match peers.read_index(&node, request).await {
+                Ok(resp) => {
+                    let _ = tx_read.send(RaftMessage::ReadReady {
+                        read_index: resp.read_index,
+                        body,
+                        tx
+                    });
+                },
+                Err(err) => {
+                    let _ = tx.send(RaftMessage::ClientError{ body: err.to_string() });
+                }
+            }
+        });
+    }
+
+    async fn serve_reads(&mut self) {
+        let applied = self.raft.last_applied;
+        let (ready, pending): (Vec<_>, Vec<_>) = std::mem::take(&mut self.pending_reads)
+            .into_iter()
+            .partition(|(read_index, _, _)| *read_index <= applied);
+        self.pending_reads = pending;
+
+        for (_, body, tx) in ready.into_iter() {
+            let tracker = self.raft.tracker.read().await;
+            let response = match tracker.propagate(&body).await {
+                Ok(body) => RaftMessage::ClientResp { body },
+                Err(err) => RaftMessage::ClientError { body: err.to_string() }
+            };
+            let _ = tx.send(response);
+        }
+    }
+
     #[instrument(level="info", skip(self))]
-    fn forward_client_request(&self, body: T,
+    fn forward_client_request(&self, body: T, session: Option<Session>,
         tx: Sender<RaftMessage<T>>, iswrite: bool) {
-        let leader = self.raft.current_leader.as_ref();
-        if let Some(id) = leader {
-            let mut iter = self.raft.get_all_nodes().into_iter().filter(|x| x.id == id.to_owned());
-            let node = iter.next().unwrap();
+        if let Some(node) = self.leader_node() {
             let payload = serde_json::to_string(&body).unwrap();
-            let request = ForwardEntryRequest { payload, iswrite };
+            let (client_id, sequence) = match session {
+                Some(session) => (session.client_id, session.sequence),
+                None => (String::new(), 0)
+            };
+            let request = ForwardEntryRequest { payload, iswrite, client_id, sequence };
+            let mut tx = tx;
+            let peers = self.raft.peers.clone();
             tokio::spawn(async move {
-                let resp = forward(&node, request).await;
+                // Dropping the call once the client gives up lets the leader forget it too.
+                let resp = tokio::select! {
+                    resp = peers.forward(&node, request) => resp,
+                    _ = tx.closed() => return
+                };
                 match resp {
                     Ok(resp) => {
                         let body = serde_json::from_str(&resp.payload).unwrap();
@@ -116,7 +280,11 @@ impl<'a, T: ClientData, R: Tracker<Entity=T>> Follower<'a, T, R> {
                         });
                     },
                     Err(err) => {
-                        let _ = tx.send(RaftMessage::ClientError{ body: err.to_string() });
+                        let body = match err.downcast_ref::<tonic::Status>() {
+                            Some(status) => status.message().to_string(),
+                            None => err.to_string()
+                        };
+                        let _ = tx.send(RaftMessage::ClientError{ body });
                     }
                 }
             });
@@ -134,14 +302,76 @@ impl<'a, T: ClientData, R: Tracker<Entity=T>> Follower<'a, T, R> {
                 let frame = tracker.commit(i).await;
                 drop(tracker);
                 match frame {
-                    Ok(_) => {
+                    Ok(entry) => {
+                        self.raft.update_last_applied(i + 1);
+                        if let LogEntry::Configuration{nodes, learners} = &entry {
+                            self.raft.apply_configuration(nodes.clone(), learners.clone())?;
+                        }
                         self.raft.update_commit_index(i + 1, true);
+                        self.raft.resolve_write(i + 1, entry);
                     },
                     Err(err) => {
                         return Err(err);
                     }
                 };
             }
+            self.raft.tracker.read().await.save_commit_index()?;
+        }
+        Ok(())
+    }
+
+    async fn log_matches(&self, index: u64, term: u64) -> bool {
+        if index > self.raft.last_index() {
+            return false;
+        }
+        let tracker = self.raft.tracker.read().await;
+        // Compacted entries are commited, so they match the leader's log.
+        if index < tracker.get_last_snapshot_index() {
+            return true;
+        }
+        tracker.get_log_term(index) == term
+    }
+
+    // Term of the conflicting entry and the first index holding that term, so
+    // the leader can skip the whole term instead of one entry per round trip.
+    async fn conflict_hint(&self, prev_log_index: u64) -> (u64, u64) {
+        if prev_log_index > self.raft.last_index() {
+            return (0, 0);
+        }
+        let tracker = self.raft.tracker.read().await;
+        let conflict_term = tracker.get_log_term(prev_log_index);
+        let conflict_index = (tracker.get_last_snapshot_index() + 1..prev_log_index).rev()
+            .take_while(|&index| tracker.get_log_term(index) == conflict_term)
+            .last()
+            .unwrap_or(prev_log_index);
+        (conflict_term, conflict_index)
+    }
+
+    async fn append_entries(&mut self, prev_log_index: u64, entries: Vec<(u64, LogEntry<T>)>)
+        -> crate::Result<()> {
+        let mut tracker = self.raft.tracker.write().await;
+        let mut truncated = None;
+        for (i, (term, entity)) in entries.into_iter().enumerate() {
+            let index = prev_log_index + 1 + i as u64;
+            if index <= tracker.get_last_snapshot_index() {
+                continue;
+            }
+            if index <= tracker.get_last_log_index() {
+                if tracker.get_log_term(index) == term {
+                    continue;
+                }
+                info!("Truncating conflicting entries from {}", index);
+                tracker.truncate_log(index)?;
+                truncated.get_or_insert(index);
+            }
+            tracker.append_log(entity, term)?;
+            info!("Recived an append entry: Appending to log");
+        }
+        let (index, term) = (tracker.get_last_log_index(), tracker.get_last_log_term());
+        drop(tracker);
+        self.raft.update_last_log(index, term);
+        if let Some(index) = truncated {
+            self.raft.fail_writes_from(index);
         }
         Ok(())
     }
@@ -154,72 +384,64 @@ impl<'a, T: ClientData, R: Tracker<Entity=T>> Follower<'a, T, R> {
                 status: None,
                 payload: Some(AppendEntriesResponse {
                     success: false,
-                    term: self.raft.current_term
+                    term: self.raft.current_term,
+                    conflict_term: 0,
+                    conflict_index: 0,
+                    last_index: self.raft.last_index()
                 })
             };
         }
-        self.raft.current_term = body.term;
-        if self.raft.last_term() != body.prev_log_term || self.raft.last_index() != body.prev_log_index {
+        if body.term > self.raft.current_term {
+            if let Err(err) = self.raft.update_hard_state(body.term, None) {
+                error!(cause = %err, "Caused an error: ");
+                return RaftMessage::AppendResp {
+                    status: Some(tonic::Status::internal("Could not persist the hard state")),
+                    payload: None
+                }
+            }
+        }
+        self.raft.heard_from_leader();
+        self.raft.current_leader = Some(body.leader_id.clone());
+        if !body.leader_client_addr.is_empty() {
+            self.raft.leader_client_addr = Some((body.leader_id.clone(), body.leader_client_addr.clone()));
+        }
+        if !self.log_matches(body.prev_log_index, body.prev_log_term).await {
             info!("Recived an append entry: False Response, last_log_term = {}, last_log_index = {}",
                 self.raft.last_term(), self.raft.last_index());
+            let (conflict_term, conflict_index) = self.conflict_hint(body.prev_log_index).await;
             return RaftMessage::AppendResp {
                 status: None,
                 payload: Some(AppendEntriesResponse {
                     success: false,
-                    term: self.raft.current_term
+                    term: self.raft.current_term,
+                    conflict_term,
+                    conflict_index,
+                    last_index: self.raft.last_index()
                 })
             };
         }
-        self.raft.current_leader = Some(body.leader_id);
-        if body.entries.len() == 0 {
-            match self.check_for_commit(self.raft.last_index(), body.leader_commit).await {
-                Ok(_) => {
-                },
+        let mut entries = Vec::new();
+        for entry in body.entries.iter() {
+            match LogEntry::<T>::from_entry(entry) {
+                Ok(entity) => entries.push((entry.term, entity)),
                 Err(err) => {
                     error!(cause = %err, "Caused an error: ");
                     return RaftMessage::AppendResp {
-                        status: Some(tonic::Status::cancelled("Coud not append to log")),
+                        status: Some(tonic::Status::cancelled("Could not parse the message")),
                         payload: None 
                     }
                 }
-            };
-
-            return RaftMessage::AppendResp {
-                status: None,
-                payload: Some(AppendEntriesResponse {
-                    success: true,
-                    term: self.raft.current_term
-                })
             }
         }
-        let entry = body.entries[0].clone();
-        let entity: T = match serde_json::from_str(&entry.payload) {
-            Ok(entity) => entity,
-            Err(err) => {
-                error!(cause = %err, "Caused an error: ");
-                return RaftMessage::AppendResp {
-                    status: Some(tonic::Status::cancelled("Could not parse the message")),
-                    payload: None 
-                }
-            }
-        };
-        let mut tracker = self.raft.tracker.write().await;
-        let last_log_index = match tracker.append_log(entity, entry.term) {
-            Ok(index) => {
-                info!("Recived an append entry: Appending to log");
-                index
-            },
-            Err(err) => {
-                error!(cause = %err, "Caused an error: ");
-                return RaftMessage::AppendResp {
-                    status: Some(tonic::Status::cancelled("Coud not append to log")),
-                    payload: None 
-                }
+        if let Err(err) = self.append_entries(body.prev_log_index, entries).await {
+            error!(cause = %err, "Caused an error: ");
+            return RaftMessage::AppendResp {
+                status: Some(tonic::Status::cancelled("Coud not append to log")),
+                payload: None 
             }
-        };
-        drop(tracker);
-        self.raft.update_last_log(last_log_index, entry.term);
-        match self.check_for_commit(last_log_index, body.leader_commit).await {
+        }
+        let last_new_index = body.prev_log_index + body.entries.len() as u64;
+        match self.check_for_commit(last_new_index, body.leader_commit).await {
             Ok(_) => {
             },
             Err(err) => {
@@ -235,7 +457,10 @@ impl<'a, T: ClientData, R: Tracker<Entity=T>> Follower<'a, T, R> {
             status: None,
             payload: Some(AppendEntriesResponse {
                 success: true,
-                term: self.raft.current_term
+                term: self.raft.current_term,
+                conflict_term: 0,
+                conflict_index: 0,
+                last_index: self.raft.last_index()
             })
         }
     }
@@ -1,24 +1,54 @@
 use crate::{Raft, ClientData, Tracker, RaftMessage, Node, NodeID};
 use crate::raft::State;
 use tracing::{instrument, error, info};
-use tokio::time::{Instant, sleep_until, Duration, sleep};
-use tokio::sync::{mpsc, RwLock, oneshot};
-use crate::raft_rpc::{AppendEntriesRequest, Entry, AppendEntriesResponse};
-use crate::raft_rpc::SnapshotRequest;
-use crate::rpc;
-use std::collections::BTreeMap;
+use tokio::time::{Instant, sleep_until, Duration, sleep, interval};
+use tokio::sync::{mpsc, RwLock, oneshot, watch};
+use tokio::task::JoinSet;
+use crate::raft_rpc::{AppendEntriesRequest, AppendEntriesResponse};
+use crate::raft_rpc::{SnapshotRequest, SnapshotResponse, ReadIndexResponse};
+use crate::raft_rpc::{TransferLeadershipRequest, TransferLeadershipResponse};
+use crate::raft_rpc::{TimeoutNowRequest, TimeoutNowResponse};
+use crate::tracker::LogEntry;
+use crate::rpc::ChannelPool;
+use std::collections::{BTreeMap, HashMap, VecDeque};
 
-use std::cmp::min;
+use std::cmp::{min, max};
 
 use std::sync::Arc;
 
 #[derive(Debug)]
 pub struct Leader <'a, T: ClientData, R: Tracker<Entity=T>> {
     raft: &'a mut Raft<T, R>,
-    replicators: Vec<mpsc::UnboundedSender<ReplicatorMsg>>,
+    replicators: HashMap<NodeID, mpsc::UnboundedSender<ReplicatorMsg>>,
+    tx_repl: mpsc::UnboundedSender<ReplicatorMsg>,
     rx_repl: mpsc::UnboundedReceiver<ReplicatorMsg>,
     commit_queue: BTreeMap<u64, oneshot::Sender<RaftMessage<T>>>,
-    shutdown_txs: Vec<oneshot::Sender<()>>
+    shutdown_txs: HashMap<NodeID, oneshot::Sender<()>>,
+    last_contact: HashMap<NodeID, Instant>,
+    tx_members: watch::Sender<(Vec<NodeID>, Vec<NodeID>)>,
+    pending_configuration: bool,
+    tx_round: watch::Sender<u64>,
+    read_round: u64,
+    acked_rounds: HashMap<NodeID, u64>,
+    lease_acks: HashMap<NodeID, Instant>,
+    pending_reads: VecDeque<PendingRead<T>>,
+    transfer: Option<Transfer<T>>,
+    noop_index: u64
+}
+
+#[derive(Debug)]
+struct Transfer<T: ClientData> {
+    target: Node,
+    deadline: Instant,
+    tx: Option<oneshot::Sender<RaftMessage<T>>>
+}
+
+#[derive(Debug)]
+struct PendingRead<T: ClientData> {
+    round: u64,
+    read_index: u64,
+    body: Option<T>,
+    tx: oneshot::Sender<RaftMessage<T>>
 }
 
 #[derive(Debug, Clone)]
@@ -29,10 +59,42 @@ enum ReplicatorMsg {
     ReplicateResp {
         next_index: u64,
         match_index: u64,
+        round: u64,
+        sent: Instant,
         id: NodeID 
     }
 }
 
+#[derive(Debug, Clone, Copy)]
+struct BatchLimits {
+    entries: u64,
+    size: u64,
+    inflight: u64,
+    snapshot_chunk: u64
+}
+
+// What every replicator of a leader shares.
+struct ReplicatorConfig<R> {
+    term: u64,
+    id: NodeID,
+    client_addr: String,
+    tracker: Arc<RwLock<R>>,
+    peers: ChannelPool,
+    tx_repl: mpsc::UnboundedSender<ReplicatorMsg>,
+    rx_round: watch::Receiver<u64>,
+    rx_members: watch::Receiver<(Vec<NodeID>, Vec<NodeID>)>,
+    heartbeat: Duration,
+    limits: BatchLimits
+}
+
+struct Inflight {
+    prev_log_index: u64,
+    entries: u64,
+    round: u64,
+    sent: Instant,
+    result: crate::Result<AppendEntriesResponse>
+}
+
 #[derive(Debug, PartialEq, Eq)]
 enum ReplicationState {
     UpToDate,
@@ -49,55 +111,118 @@ struct Replicator <T: ClientData, R: Tracker<Entity=T>> {
     node: Node,
     tracker: Arc<RwLock<R>>,
     id: NodeID,
+    client_addr: String,
+    peers: ChannelPool,
     state: ReplicationState,
     rx_repl: mpsc::UnboundedReceiver<ReplicatorMsg>,
     tx_repl: mpsc::UnboundedSender<ReplicatorMsg>,
-    heartbeat: Duration
+    rx_round: watch::Receiver<u64>,
+    rx_members: watch::Receiver<(Vec<NodeID>, Vec<NodeID>)>,
+    round: u64,
+    sent: Instant,
+    heartbeat: Duration,
+    limits: BatchLimits
 }
 
 impl<'a, T: ClientData, R: Tracker<Entity=T>> Leader<'a, T, R> {
     pub fn new(raft:&'a mut Raft<T, R>) -> Leader<T, R> {
-        let mut replicators = Vec::new();
+        let (tx_repl, rx_core_repl) = mpsc::unbounded_channel();
 
-        let mut shutdown_txs = Vec::new();
+        let (tx_round, _) = watch::channel(0);
 
-        let (tx_repl, rx_core_repl) = mpsc::unbounded_channel();
+        let (tx_members, _) = watch::channel((raft.members(), raft.learner_members()));
 
-        for node in raft.get_all_nodes().into_iter() {
-            let (tx_core_repl, rx_repl) = mpsc::unbounded_channel();
-            let (tx_shutdown, rx_shutdown) = oneshot::channel();
-            shutdown_txs.push(tx_shutdown);
-            let match_index = if let Some(state) = raft.nodes_state.get(&node.id) {
-                state.match_index
-            } else {
-                0
-            };
-            let mut replicator = Replicator::new(node, raft.last_index() + 1,
-                match_index, raft.current_term, raft.tracker.clone(), raft.id.clone(),
-                rx_repl, tx_repl.clone(), raft.heartbeat);
+        raft.current_leader = Some(raft.id.clone());
 
-            tokio::spawn(async move {
-                tokio::select! {
-                    _ = replicator.run() => {
-                    },
-                    _ = rx_shutdown => {
-                    }
-                }
-            });
+        let commit_queue = raft.take_pending_writes();
+
+        let nodes = raft.get_all_nodes().into_iter().chain(raft.get_all_learners());
+
+        let mut leader = Leader { raft, replicators: HashMap::new(), tx_repl, rx_repl: rx_core_repl,
+        commit_queue, shutdown_txs: HashMap::new(), last_contact: HashMap::new(),
+        tx_members, pending_configuration: false, tx_round, read_round: 0,
+        acked_rounds: HashMap::new(), lease_acks: HashMap::new(), pending_reads: VecDeque::new(),
+        transfer: None, noop_index: 0};
 
-            replicators.push(tx_core_repl);
+        for node in nodes {
+            leader.spawn_replicator(node);
         }
 
-        Leader { raft, replicators, rx_repl: rx_core_repl,
-        commit_queue: BTreeMap::new(), shutdown_txs}
+        leader
+    }
+
+    fn spawn_replicator(&mut self, node: Node) {
+        self.last_contact.insert(node.id.clone(), Instant::now());
+        let (tx_core_repl, rx_repl) = mpsc::unbounded_channel();
+        let (tx_shutdown, rx_shutdown) = oneshot::channel();
+        let match_index = if let Some(state) = self.raft.nodes_state.get(&node.id) {
+            state.match_index
+        } else {
+            0
+        };
+        let id = node.id.clone();
+        let limits = BatchLimits {
+            entries: max(self.raft.max_batch_entries, 1),
+            size: self.raft.max_batch_size,
+            inflight: max(self.raft.max_inflight, 1),
+            snapshot_chunk: max(self.raft.snapshot_chunk_size, 1)
+        };
+        let config = ReplicatorConfig {
+            term: self.raft.current_term,
+            id: self.raft.id.clone(),
+            client_addr: self.raft.client_addr.clone(),
+            tracker: self.raft.tracker.clone(),
+            peers: self.raft.peers.clone(),
+            tx_repl: self.tx_repl.clone(),
+            rx_round: self.tx_round.subscribe(),
+            rx_members: self.tx_members.subscribe(),
+            heartbeat: self.raft.heartbeat,
+            limits
+        };
+        let mut replicator = Replicator::new(node, self.raft.last_index() + 1, match_index,
+            rx_repl, config);
+
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = replicator.run() => {
+                },
+                _ = rx_shutdown => {
+                }
+            }
+        });
+
+        self.replicators.insert(id.clone(), tx_core_repl);
+        self.shutdown_txs.insert(id, tx_shutdown);
     }
 
     #[instrument(level="info", skip(self))]
     pub async fn run(&mut self) -> crate::Result<()> {
         info!("Running at Leader State");
         info!("Current term is {}.", self.raft.current_term);
+        let result = self.lead().await;
+        // However we stopped leading, reads still waiting are never answered.
+        for read in std::mem::take(&mut self.pending_reads) {
+            let _ = read.tx.send(self.raft.not_leader_error());
+        }
+        self.raft.park_writes(std::mem::take(&mut self.commit_queue));
+        self.raft.metrics.clear_followers();
+        result
+    }
+
+    async fn lead(&mut self) -> crate::Result<()> {
+        let mut check_quorum = interval(Duration::from_millis(self.raft.election_timeout));
+        // Entries from earlier terms only commit along with one from ours.
+        if self.raft.last_term() != self.raft.current_term {
+            self.append_entry(LogEntry::Noop, None).await?;
+        }
+        self.noop_index = self.raft.last_index();
         while self.is_leader() {
             tokio::select! {
+                _ = check_quorum.tick() => {
+                    self.check_quorum();
+                    self.check_transfer();
+                    self.drop_abandoned_requests();
+                },
                 Some(request) = self.raft.rx_rpc.recv() => 
                     self.handle_api_request(request).await?,
                 Some(request) = self.rx_repl.recv() =>  {
@@ -116,30 +241,105 @@ impl<'a, T: ClientData, R: Tracker<Entity=T>> Leader<'a, T, R> {
        match request {
             RaftMessage::ClientReadMsg {body, tx} => {
                 info!("Received A client read message.");
-                let tracker = self.raft.tracker.read().await;
-                let response = tracker.propagate(&body).await?;
-                info!("Received Response {:?}", response);
-                if let Err(_) = tx.send(RaftMessage::ClientResp { body: response }) {
-                    error!("Peer drop the client response");
+                if self.raft.lease_read && self.has_lease() {
+                    self.lease_read(body, tx).await?;
+                } else {
+                    self.read_index(Some(body), tx).await?;
                 }
             },
-            RaftMessage::ClientWriteMsg {body, tx} => {
+            RaftMessage::ReadIndexMsg {body, tx} => {
+                info!("Recived a read index msg from {}", body.node_id);
+                self.read_index(None, tx).await?;
+            },
+            RaftMessage::ClientWriteMsg {tx, ..} if self.transfer.is_some() => {
+                let _ = tx.send(RaftMessage::ClientError { body: "Leadership transfer in progress".into() });
+            },
+            RaftMessage::ClientWriteMsg {body, session, tx} => {
                 info!("Received A client write message.");
-                let mut tracker = self.raft.tracker.write().await;
-                let index = tracker.append_log(body, self.raft.current_term)?;
-                drop(tracker);
-                self.raft.update_last_log(index, self.raft.current_term);
-                self.commit_queue.insert(index, tx);
-                let repl_req = ReplicatorMsg::ReplicateReq{index};
-                for replicator in &self.replicators {
-                    info!("Sending to {:?}", replicator);
-                    let _ = replicator.send(repl_req.clone());
+                let entry = match session {
+                    Some(session) => LogEntry::Session{ session, entity: body },
+                    None => LogEntry::Normal(body)
+                };
+                self.append_entry(entry, Some(tx)).await?;
+            },
+            RaftMessage::AddServerMsg {tx, ..} | RaftMessage::RemoveServerMsg {tx, ..}
+                if self.transfer.is_some() => {
+                let _ = tx.send(RaftMessage::MembershipResp {
+                    payload: None,
+                    status: Some(tonic::Status::unavailable("Leadership transfer in progress"))
+                });
+            },
+            RaftMessage::AddServerMsg {body, tx} => {
+                info!("Recived an add server msg for {}", body.node_id);
+                match body.node_id.parse::<Node>() {
+                    Ok(node) => {
+                        let mut nodes = self.raft.members();
+                        let mut learners = self.raft.learner_members();
+                        nodes.retain(|id| *id != node.id);
+                        learners.retain(|id| *id != node.id);
+                        if body.learner {
+                            learners.push(node.id);
+                        } else {
+                            nodes.push(node.id);
+                        }
+                        self.change_configuration(nodes, learners, tx).await?;
+                    },
+                    Err(_) => {
+                        let _ = tx.send(RaftMessage::MembershipResp {
+                            payload: None,
+                            status: Some(tonic::Status::invalid_argument("Could not parse the node id"))
+                        });
+                    }
                 }
             },
+            RaftMessage::RemoveServerMsg {body, tx} => {
+                info!("Recived a remove server msg for {}", body.node_id);
+                let nodes = self.raft.members().into_iter()
+                    .filter(|id| *id != body.node_id)
+                    .collect();
+                let learners = self.raft.learner_members().into_iter()
+                    .filter(|id| *id != body.node_id)
+                    .collect();
+                self.change_configuration(nodes, learners, tx).await?;
+            },
             RaftMessage::VoteMsg{body, tx} => {
                 info!("Recived a vote msg from {}", body.candidate_id);
                 let _ = tx.send(self.raft.handle_vote_request(body));
+            },
+            RaftMessage::PreVoteMsg{body, tx} => {
+                info!("Recived a pre vote msg from {}", body.candidate_id);
+                let _ = tx.send(self.raft.handle_pre_vote_request(body));
+            },
+            RaftMessage::TransferLeaderMsg{body, tx} => {
+                info!("Recived a transfer leadership msg to {}", body.node_id);
+                self.transfer_leadership(body, tx);
+            },
+            RaftMessage::AppendMsg{body, tx} => {
+                self.observe_leader(body.term, body.leader_id)?;
+                let _ = tx.send(RaftMessage::AppendResp {
+                    status: Some(tonic::Status::cancelled("Node is in Leader state")),
+                    payload: None
+                });
+            },
+            RaftMessage::InstallSnapshot{body, tx} => {
+                self.observe_leader(body.term, body.leader_id)?;
+                let _ = tx.send(RaftMessage::InstallSnapshotResp {
+                    status: Some(tonic::Status::cancelled("Node is in Leader state")),
+                    payload: SnapshotResponse { term: self.raft.current_term, offset: 0, done: false }
+                });
+            },
+            RaftMessage::TimeoutNowMsg{tx, ..} => {
+                let _ = tx.send(RaftMessage::TimeoutNowResp {
+                    payload: TimeoutNowResponse { term: self.raft.current_term },
+                    status: Some(tonic::Status::failed_precondition("Node is the leader"))
+                });
             }
+            RaftMessage::StatusMsg{tx} => {
+                let _ = tx.send(self.raft.status_response().await);
+            },
+            RaftMessage::TakeSnapshotMsg{tx} => {
+                let _ = tx.send(self.raft.snapshot_response().await);
+            },
            _ => unreachable!()
        }
        Ok(())
@@ -148,69 +348,395 @@ impl<'a, T: ClientData, R: Tracker<Entity=T>> Leader<'a, T, R> {
     #[instrument(level="info", skip(self))]
     async fn handle_replicator_resp(&mut self, request: ReplicatorMsg) -> crate::Result<()> {
         match request {
-            ReplicatorMsg::ReplicateResp{next_index, match_index, id} => {
+            ReplicatorMsg::ReplicateResp{next_index, match_index, round, sent, id} => {
                 info!("Recived A Replicator message");
+                if !self.replicators.contains_key(&id) {
+                    return Ok(());
+                }
+                self.last_contact.insert(id.clone(), Instant::now());
+                let lease = self.lease_acks.entry(id.clone()).or_insert(sent);
+                *lease = std::cmp::max(*lease, sent);
+                let acked = self.acked_rounds.entry(id.clone()).or_insert(0);
+                *acked = std::cmp::max(*acked, round);
+                let last_index = self.raft.last_index();
                 let node_state = self.raft.nodes_state.get_mut(&id);
                 if let Some(state) = node_state {
                     state.next_index = next_index;
                     state.match_index = match_index;
+                    self.raft.metrics.set_follower(&id, state, last_index);
                 }
                 self.check_for_commit(match_index).await?;
+                self.serve_reads().await?;
+                self.check_transfer();
             },
             _ => unreachable!()
         }
         Ok(())
     }
 
+    async fn append_entry(&mut self, entry: LogEntry<T>, tx: Option<oneshot::Sender<RaftMessage<T>>>)
+        -> crate::Result<()> {
+        let mut tracker = self.raft.tracker.write().await;
+        let index = tracker.append_log(entry, self.raft.current_term)?;
+        drop(tracker);
+        self.raft.update_last_log(index, self.raft.current_term);
+        if let Some(tx) = tx {
+            self.commit_queue.insert(index, tx);
+        }
+        let repl_req = ReplicatorMsg::ReplicateReq{index};
+        for replicator in self.replicators.values() {
+            info!("Sending to {:?}", replicator);
+            let _ = replicator.send(repl_req.clone());
+        }
+        self.check_for_commit(index).await
+    }
+
+    // Membership changes one server at a time, so the old and the new
+    // majorities always overlap and the entry can be applied on commit.
+    async fn change_configuration(&mut self, mut nodes: Vec<NodeID>, mut learners: Vec<NodeID>,
+        tx: oneshot::Sender<RaftMessage<T>>) -> crate::Result<()> {
+        nodes.sort();
+        nodes.dedup();
+        learners.sort();
+        learners.dedup();
+        if nodes == self.raft.members() && learners == self.raft.learner_members() {
+            let _ = tx.send(self.raft.membership_response(true));
+            return Ok(());
+        }
+
+        let tracker = self.raft.tracker.read().await;
+        let commit_term = tracker.get_log_term(self.raft.get_commit_index());
+        drop(tracker);
+        if self.pending_configuration || commit_term != self.raft.current_term {
+            let _ = tx.send(RaftMessage::MembershipResp {
+                payload: None,
+                status: Some(tonic::Status::unavailable("A configuration change is in progress"))
+            });
+            return Ok(());
+        }
+
+        self.pending_configuration = true;
+        self.append_entry(LogEntry::Configuration{ nodes, learners }, Some(tx)).await
+    }
+
+    fn apply_configuration(&mut self, nodes: Vec<NodeID>, learners: Vec<NodeID>) -> crate::Result<()> {
+        let (added, removed) = self.raft.apply_configuration(nodes, learners)?;
+        for id in removed.iter() {
+            self.replicators.remove(id);
+            if let Some(tx) = self.shutdown_txs.remove(id) {
+                let _ = tx.send(());
+            }
+            self.last_contact.remove(id);
+            self.acked_rounds.remove(id);
+            self.lease_acks.remove(id);
+        }
+        for node in added.into_iter() {
+            self.spawn_replicator(node);
+        }
+        self.tx_members.send_replace((self.raft.members(), self.raft.learner_members()));
+        self.pending_configuration = false;
+        Ok(())
+    }
+
+    fn transfer_leadership(&mut self, body: TransferLeadershipRequest,
+        tx: oneshot::Sender<RaftMessage<T>>) {
+        let target = if body.node_id.is_empty() {
+            self.raft.nodes.iter()
+                .max_by_key(|node| self.raft.nodes_state.get(&node.id).map(|s| s.match_index))
+                .cloned()
+        } else {
+            self.raft.nodes.iter().find(|node| node.id == body.node_id).cloned()
+        };
+        let target = match target {
+            Some(target) => target,
+            None => {
+                let _ = tx.send(RaftMessage::TransferLeaderResp {
+                    payload: None,
+                    status: Some(tonic::Status::invalid_argument("The target is not a voting member"))
+                });
+                return;
+            }
+        };
+        if self.transfer.is_some() {
+            let _ = tx.send(RaftMessage::TransferLeaderResp {
+                payload: None,
+                status: Some(tonic::Status::unavailable("Leadership transfer in progress"))
+            });
+            return;
+        }
+
+        info!("Transfering the leadership to {}", target.id);
+        self.transfer = Some(Transfer {
+            target,
+            deadline: Instant::now() + Duration::from_millis(self.raft.election_timeout),
+            tx: Some(tx)
+        });
+        self.check_transfer();
+    }
+
+    // Writes are rejected during the transfer, so once the target has matched
+    // the last index it is safe to ask it to start an election.
+    fn check_transfer(&mut self) {
+        let last_index = self.raft.last_index();
+        let transfer = match &mut self.transfer {
+            Some(transfer) => transfer,
+            None => return
+        };
+        if transfer.deadline <= Instant::now() {
+            info!("Leadership transfer to {} timed out", transfer.target.id);
+            if let Some(tx) = transfer.tx.take() {
+                let _ = tx.send(RaftMessage::TransferLeaderResp {
+                    payload: None,
+                    status: Some(tonic::Status::deadline_exceeded("The target did not catch up"))
+                });
+            }
+            self.transfer = None;
+            // The target may still have been asked to time out, only acks to
+            // heartbeats sent from now on can renew the lease.
+            self.lease_acks.clear();
+            return;
+        }
+        let caught_up = self.raft.nodes_state.get(&transfer.target.id)
+            .is_some_and(|state| state.match_index >= last_index);
+        if !caught_up {
+            return;
+        }
+        let tx = match transfer.tx.take() {
+            Some(tx) => tx,
+            None => return
+        };
+
+        let target = transfer.target.clone();
+        let peers = self.raft.peers.clone();
+        let request = TimeoutNowRequest {
+            term: self.raft.current_term,
+            leader_id: self.raft.id.clone()
+        };
+        tokio::spawn(async move {
+            let response = match peers.timeout_now(&target, request).await {
+                Ok(_) => RaftMessage::TransferLeaderResp {
+                    payload: Some(TransferLeadershipResponse {
+                        success: true,
+                        leader_id: target.id
+                    }),
+                    status: None
+                },
+                Err(err) => RaftMessage::TransferLeaderResp {
+                    payload: None,
+                    status: Some(tonic::Status::unavailable(err.to_string()))
+                }
+            };
+            let _ = tx.send(response);
+        });
+    }
+
+    // Learners are replicated to but never count towards a quorum.
+    fn voters_progress<V: Clone>(&self, progress: &HashMap<NodeID, V>) -> Vec<V> {
+        self.raft.nodes.iter()
+            .filter_map(|node| progress.get(&node.id).cloned())
+            .collect()
+    }
+
     fn is_leader(&self) -> bool {
         self.raft.state == State::Leader
     }
 
+    fn check_quorum(&mut self) {
+        let timeout = Duration::from_millis(self.raft.election_timeout);
+        let contacted = self.voters_progress(&self.last_contact).into_iter()
+            .filter(|contact| contact.elapsed() < timeout)
+            .count();
+        if 2 * (contacted + 1) > self.raft.nodes.len() + 1 {
+            return;
+        }
+
+        info!("Lost contact with the quorum, stepping down.");
+        self.step_down("Leader lost the quorum");
+    }
+
+    // Clients that timed out dropped their receivers. Their entries still
+    // commit, but there is nobody left to answer.
+    fn drop_abandoned_requests(&mut self) {
+        self.commit_queue.retain(|_, tx| !tx.is_closed());
+        self.pending_reads.retain(|read| !read.tx.is_closed());
+    }
+
+    fn step_down(&mut self, reason: &str) {
+        self.raft.set_state(self.raft.follower_state());
+        self.raft.current_leader = None;
+        for read in std::mem::take(&mut self.pending_reads) {
+            let _ = read.tx.send(RaftMessage::ClientError { body: reason.into() });
+        }
+    }
+
+    // The new leader retries the rejected request once we are a follower.
+    fn observe_leader(&mut self, term: u64, leader_id: NodeID) -> crate::Result<()> {
+        if term <= self.raft.current_term {
+            return Ok(());
+        }
+        info!("Found a leader with a higher term, stepping down.");
+        self.raft.update_hard_state(term, None)?;
+        self.step_down("Leader found a higher term");
+        self.raft.current_leader = Some(leader_id);
+        Ok(())
+    }
+
+    async fn read_index(&mut self, body: Option<T>, tx: oneshot::Sender<RaftMessage<T>>)
+        -> crate::Result<()> {
+        self.read_round += 1;
+        self.tx_round.send_replace(self.read_round);
+        self.pending_reads.push_back(PendingRead {
+            round: self.read_round,
+            read_index: self.read_index_floor(),
+            body,
+            tx
+        });
+        self.serve_reads().await
+    }
+
+    // Until our no-op commits we may not know the latest commited index, so
+    // reads wait for it to be applied.
+    fn read_index_floor(&self) -> u64 {
+        max(self.raft.get_commit_index(), self.noop_index)
+    }
+
+    fn confirmed_round(&self) -> u64 {
+        let needed = self.raft.nodes.len().div_ceil(2);
+        if needed == 0 {
+            return self.read_round;
+        }
+        let mut rounds = self.voters_progress(&self.acked_rounds);
+        rounds.sort_unstable_by(|a, b| b.cmp(a));
+        rounds.get(needed - 1).cloned().unwrap_or(0)
+    }
+
+    // The followers will not start an election before election_timeout passes
+    // from the heartbeat they acked, so the lease is counted from its send time.
+    fn has_lease(&self) -> bool {
+        // The target of a transfer skips pre-vote, so it may win a newer term
+        // while our lease still looks valid.
+        if self.transfer.is_some() {
+            return false;
+        }
+        let needed = self.raft.nodes.len().div_ceil(2);
+        if needed == 0 {
+            return true;
+        }
+        let mut sent = self.voters_progress(&self.lease_acks);
+        sent.sort_unstable_by(|a, b| b.cmp(a));
+        let lease = Duration::from_millis(
+            self.raft.election_timeout.saturating_sub(self.raft.clock_drift));
+        match sent.get(needed - 1) {
+            Some(sent) => sent.elapsed() < lease,
+            None => false
+        }
+    }
+
+    async fn lease_read(&mut self, body: T, tx: oneshot::Sender<RaftMessage<T>>)
+        -> crate::Result<()> {
+        self.pending_reads.push_back(PendingRead {
+            round: 0,
+            read_index: self.read_index_floor(),
+            body: Some(body),
+            tx
+        });
+        self.serve_reads().await
+    }
+
+    async fn serve_reads(&mut self) -> crate::Result<()> {
+        let confirmed = self.confirmed_round();
+        while let Some(read) = self.pending_reads.front() {
+            if read.round > confirmed || read.read_index > self.raft.last_applied {
+                break;
+            }
+            let read = self.pending_reads.pop_front().unwrap();
+            let response = match read.body {
+                Some(body) => {
+                    let tracker = self.raft.tracker.read().await;
+                    let response = tracker.propagate(&body).await?;
+                    info!("Received Response {:?}", response);
+                    RaftMessage::ClientResp { body: response }
+                },
+                None => RaftMessage::ReadIndexResp {
+                    payload: Some(ReadIndexResponse {
+                        term: self.raft.current_term,
+                        read_index: read.read_index
+                    }),
+                    status: None
+                }
+            };
+            if read.tx.send(response).is_err() {
+                error!("Peer drop the client response");
+            }
+        }
+        Ok(())
+    }
+
     #[instrument(level="info", skip(self))]
     async fn check_for_commit(&mut self, index: u64) -> crate::Result<()> {
         info!("Checking possible commit.");
-        if self.raft.get_commit_index() < index {
-            let number = self.raft.nodes_state.values()
-                .into_iter()
+        let term = self.raft.tracker.read().await.get_log_term(index);
+        if self.raft.get_commit_index() < index && term == self.raft.current_term {
+            let number = self.raft.nodes.iter()
+                .filter_map(|node| self.raft.nodes_state.get(&node.id))
                 .fold(0, |acc, s| if s.match_index >= index {acc + 1} else {acc});
             info!("matched number is {}", number);
 
 
-            if number >= self.raft.nodes.len() / 2 {
+            if 2 * (number + 1) > self.raft.nodes.len() + 1 {
                 for i in self.raft.get_commit_index()..index {
                     info!("Commiting index {}.", i);
                     let mut tracker = self.raft.tracker.write().await;
-                    let frame = tracker.commit(i).await?;
+                    let entry = tracker.commit(i).await?;
                     drop(tracker);
+                    self.raft.update_last_applied(i + 1);
                     self.raft.update_commit_index(i + 1, true);
+                    let response = match entry {
+                        LogEntry::Normal(body) | LogEntry::Session{entity: body, ..} =>
+                            RaftMessage::ClientResp{ body },
+                        LogEntry::Noop => continue,
+                        LogEntry::Configuration{nodes, learners} => {
+                            self.apply_configuration(nodes, learners)?;
+                            self.raft.membership_response(true)
+                        }
+                    };
                     if let Some(tx) = self.commit_queue.remove(&(i + 1)) {
-                        let _ = tx.send(RaftMessage::ClientResp{ body: frame });
+                        let _ = tx.send(response);
                     }
                 }
+                self.raft.tracker.read().await.save_commit_index()?;
             }
         }
+        if !self.raft.is_member() {
+            info!("Removed from the cluster, stepping down.");
+            self.step_down("Leader removed from the cluster");
+        }
         Ok(())
     }
 
 }
 
 impl<T: ClientData, R: Tracker<Entity=T>> Replicator<T, R> {
-    pub fn new(node: Node, next_index: u64, match_index: u64, term: u64,
-        tracker: Arc<RwLock<R>>, id: NodeID,
-        rx_repl: mpsc::UnboundedReceiver<ReplicatorMsg>, 
-        tx_repl: mpsc::UnboundedSender<ReplicatorMsg>, heartbeat: Duration)
-        -> Replicator<T, R> {
+    pub fn new(node: Node, next_index: u64, match_index: u64,
+        rx_repl: mpsc::UnboundedReceiver<ReplicatorMsg>, config: ReplicatorConfig<R>) -> Replicator<T, R> {
         Replicator {
             node,
             next_index,
-            term,
-            tracker,
-            id,
+            term: config.term,
+            tracker: config.tracker,
+            id: config.id,
+            client_addr: config.client_addr,
+            peers: config.peers,
             match_index,
             state: ReplicationState::UpToDate,
             rx_repl,
-            tx_repl,
-            heartbeat
+            tx_repl: config.tx_repl,
+            rx_round: config.rx_round,
+            rx_members: config.rx_members,
+            round: 0,
+            sent: Instant::now(),
+            heartbeat: config.heartbeat,
+            limits: config.limits
         }
     }
 
@@ -234,51 +760,105 @@ impl<T: ClientData, R: Tracker<Entity=T>> Replicator<T, R> {
         let request = AppendEntriesRequest {
             term: self.term,
             leader_id: self.id.to_string(),
+            leader_client_addr: self.client_addr.clone(),
             prev_log_index: tracker.get_last_log_index(),
             prev_log_term: tracker.get_last_log_term(),
             entries: vec![],
             leader_commit: tracker.get_last_commited_index()
         };
         drop(tracker);
-        let node = self.get_node();
 //        info!("beating for {} with {:?}", node.id, request);
-        let response = rpc::append_entries(&node, request).await?;
+        let prev_log_index = request.prev_log_index;
+        let response = self.send_append(request).await?;
         if !response.success {
             self.state = ReplicationState::Lagged;
-            self.next_index -= 1;
+            self.next_index = self.backtrack(prev_log_index, &response).await;
         }
         Ok(())
     }
 
-    pub async fn creat_append_request(&mut self, index: u64) -> crate::Result<AppendEntriesRequest> {
+    pub async fn creat_append_request(&mut self, from: u64) -> crate::Result<AppendEntriesRequest> {
         let tracker = self.tracker.read().await;
-        if self.next_index <= tracker.get_last_snapshot_index() {
+        if from <= tracker.get_last_snapshot_index() {
             self.state = ReplicationState::NeedSnappshot;
             return Err("Snapshot has been taken".into());
         }
-        let entity = tracker.get_log_entity(index);
-        let term = tracker.get_log_term(index);
-        let s_entity = serde_json::to_string(&entity)?; 
-        let entry = Entry {
-            payload: s_entity,
-            term
-        };
+        let last = min(tracker.get_last_log_index(), from + self.limits.entries - 1);
+        let mut entries = Vec::new();
+        let mut size = 0;
+        for index in from..=last {
+            let entry = tracker.get_log_entity(index).to_entry(tracker.get_log_term(index))?;
+            size += entry.payload.len() as u64;
+            // A batch always carries at least one entry, however large.
+            if !entries.is_empty() && size > self.limits.size {
+                break;
+            }
+            entries.push(entry);
+        }
         Ok(AppendEntriesRequest {
             term: self.term,
             leader_id: self.id.to_string(),
-            prev_log_index: self.next_index - 1,
-            prev_log_term: tracker.get_log_term(self.next_index - 1),
-            entries: vec![entry],
+            leader_client_addr: self.client_addr.clone(),
+            prev_log_index: from - 1,
+            prev_log_term: tracker.get_log_term(from - 1),
+            entries,
             leader_commit: tracker.get_last_commited_index()
         })
     }
 
-    pub async fn append_entry(&mut self, index: u64) 
+    // Picks the next index to probe from the follower's conflict hints. Never
+    // goes past the rejected prev_log_index or below what the follower acked.
+    async fn backtrack(&self, prev_log_index: u64, response: &AppendEntriesResponse) -> u64 {
+        let next_index = if response.conflict_term == 0 {
+            if response.last_index < prev_log_index {
+                response.last_index + 1
+            } else {
+                prev_log_index
+            }
+        } else {
+            let tracker = self.tracker.read().await;
+            (tracker.get_last_snapshot_index() + 1..prev_log_index).rev()
+                .map(|index| (index, tracker.get_log_term(index)))
+                .take_while(|&(_, term)| term >= response.conflict_term)
+                .find(|&(_, term)| term == response.conflict_term)
+                .map_or(response.conflict_index, |(index, _)| index + 1)
+        };
+        max(min(next_index, prev_log_index), self.match_index + 1)
+    }
+
+    fn pipeline(&self, request: AppendEntriesRequest) -> impl std::future::Future<Output = Inflight> {
+        let node = self.get_node();
+        let round = *self.rx_round.borrow();
+        let peers = self.peers.clone();
+        async move {
+            let prev_log_index = request.prev_log_index;
+            let entries = request.entries.len() as u64;
+            let sent = Instant::now();
+            let result = peers.append_entries(&node, request).await;
+            Inflight { prev_log_index, entries, round, sent, result }
+        }
+    }
+
+    async fn send_append(&mut self, request: AppendEntriesRequest)
         -> crate::Result<AppendEntriesResponse> {
-        let request = self.creat_append_request(index).await?; 
+        self.round = *self.rx_round.borrow();
+        self.sent = Instant::now();
         let node = self.get_node();
-        info!("sending append_entries for {} with {:?}", node.id, request);
-        let response = rpc::append_entries(&node, request).await?;
+        let response = self.peers.append_entries(&node, request).await?;
+        if response.term <= self.term {
+            self.report();
+        }
+        Ok(response)
+    }
+
+    async fn send_snapshot(&mut self, request: SnapshotRequest) -> crate::Result<SnapshotResponse> {
+        self.round = *self.rx_round.borrow();
+        self.sent = Instant::now();
+        let node = self.get_node();
+        let response = self.peers.install_snapshot(&node, request).await?;
+        if response.term <= self.term {
+            self.report();
+        }
         Ok(response)
     }
 
@@ -286,6 +866,16 @@ impl<T: ClientData, R: Tracker<Entity=T>> Replicator<T, R> {
         self.node.clone()
     }
 
+    fn report(&self) {
+        let _ = self.tx_repl.send(ReplicatorMsg::ReplicateResp {
+            match_index: self.match_index,
+            next_index: self.next_index,
+            round: self.round,
+            sent: self.sent,
+            id: self.node.id.clone()
+        });
+    }
+
 }
 
 struct Lagged<'a, T: ClientData, R: Tracker<Entity=T>> {
@@ -321,14 +911,14 @@ impl<'a, T: ClientData, R: Tracker<Entity=T>> Lagged<'a, T, R> {
             let request = AppendEntriesRequest {
                 term: self.replicator.term,
                 leader_id: self.replicator.id.to_string(),
+                leader_client_addr: self.replicator.client_addr.clone(),
                 prev_log_index: self.replicator.next_index - 1,
                 prev_log_term: tracker.get_log_term(self.replicator.next_index - 1),
                 entries: vec![],
                 leader_commit: tracker.get_last_commited_index()
             };
             drop(tracker);
-            let node = self.replicator.get_node();
-            let result = rpc::append_entries(&node, request).await;
+            let result = self.replicator.send_append(request).await;
             let response = match result {
                 Ok(resp) => resp,
                 Err(err) => {
@@ -342,7 +932,8 @@ impl<'a, T: ClientData, R: Tracker<Entity=T>> Lagged<'a, T, R> {
                 self.replicator.state = ReplicationState::Updating;
                 break;
             }
-            self.replicator.next_index -= 1;
+            self.replicator.next_index = self.replicator
+                .backtrack(self.replicator.next_index - 1, &response).await;
         }
     }
 }
@@ -365,40 +956,89 @@ impl<'a, T: ClientData, R: Tracker<Entity=T>> Updating<'a, T, R> {
             "Replicator running at Updating state."
             );
         let mut backoff = Duration::from_millis(1);
+        let mut inflight = JoinSet::new();
+        let mut rejected: Option<(u64, AppendEntriesResponse)> = None;
+        let mut failed = false;
         while self.replicator.state == ReplicationState::Updating {
-            let tracker = self.replicator.tracker.read().await;
-            let last_log_index = tracker.get_last_log_index();
-            if self.replicator.next_index > last_log_index {
-                self.replicator.state = ReplicationState::UpToDate;
-                break;
+            let last_log_index = self.replicator.tracker.read().await.get_last_log_index();
+            if rejected.is_none() && !failed && self.replicator.next_index <= last_log_index
+                && (inflight.len() as u64) < self.replicator.limits.inflight {
+                match self.replicator.creat_append_request(self.replicator.next_index).await {
+                    Ok(request) => {
+                        info!("sending append_entries for {} from {} with {} entries",
+                            self.replicator.node.id, self.replicator.next_index, request.entries.len());
+                        self.replicator.next_index += request.entries.len() as u64;
+                        inflight.spawn(self.replicator.pipeline(request));
+                        continue;
+                    },
+                    Err(err) => {
+                        error!(cause = %err, "Caused an error: ");
+                        failed = true;
+                        continue;
+                    }
+                }
             }
-            drop(tracker);
-            let response = match self.replicator
-                .append_entry(self.replicator.next_index).await {
-                Ok(resp) => {
-                    backoff = Duration::from_millis(1);
-                    resp
+            let Inflight { prev_log_index, entries, round, sent, result } = match inflight.join_next().await {
+                Some(Ok(done)) => done,
+                Some(Err(err)) => {
+                    error!(cause = %err, "Caused an error: ");
+                    failed = true;
+                    continue;
                 },
+                None => {
+                    self.settle(rejected.take(), std::mem::take(&mut failed), &mut backoff).await;
+                    continue;
+                }
+            };
+            let response = match result {
+                Ok(resp) => resp,
                 Err(err) => {
                     error!(cause = %err, "Caused an error: ");
-                    sleep(backoff).await;
-                    backoff = min(backoff * 2, self.replicator.heartbeat);
+                    failed = true;
                     continue;
                 }
             };
-            if !response.success {
-                self.replicator.next_index -=1;
-                self.replicator.state = ReplicationState::Lagged;
-                break;
+            let report = response.term <= self.replicator.term;
+            if response.success {
+                backoff = Duration::from_millis(1);
+                self.replicator.match_index = max(self.replicator.match_index,
+                    prev_log_index + entries);
+            } else if rejected.as_ref().is_none_or(|(prev, _)| prev_log_index < *prev) {
+                rejected = Some((prev_log_index, response));
             }
-            self.replicator.match_index = self.replicator.next_index;
-            self.replicator.next_index += 1;
+            if report {
+                self.replicator.round = round;
+                self.replicator.sent = sent;
+                self.replicator.report();
+            }
+        }
+    }
 
-            let _ = self.replicator.tx_repl.send(ReplicatorMsg::ReplicateResp {
-                match_index: self.replicator.match_index,
-                next_index: self.replicator.next_index,
-                id: self.replicator.node.id.clone()
-            });
+    // Runs once every in-flight request has been answered, so match_index
+    // reflects all the batches the follower accepted.
+    async fn settle(&mut self, rejected: Option<(u64, AppendEntriesResponse)>, failed: bool,
+        backoff: &mut Duration) {
+        if self.replicator.state != ReplicationState::Updating {
+            return;
+        }
+        match rejected {
+            // Later batches may overtake earlier ones, so a rejection below
+            // match_index only means the follower saw them out of order.
+            Some((prev_log_index, response)) if prev_log_index > self.replicator.match_index => {
+                self.replicator.next_index = self.replicator.backtrack(prev_log_index, &response).await;
+                self.replicator.state = ReplicationState::Lagged;
+            },
+            Some(_) => {
+                self.replicator.next_index = self.replicator.match_index + 1;
+            },
+            None if failed => {
+                self.replicator.next_index = self.replicator.match_index + 1;
+                sleep(*backoff).await;
+                *backoff = min(*backoff * 2, self.replicator.heartbeat);
+            },
+            None => {
+                self.replicator.state = ReplicationState::UpToDate;
+            }
         }
     }
 }
@@ -426,6 +1066,9 @@ impl<'a, T: ClientData, R: Tracker<Entity=T>> UpToDate<'a, T, R> {
                 _ = timeout => { 
                     let _ = self.replicator.beat().await;
                 },
+                Ok(_) = self.replicator.rx_round.changed() => {
+                    let _ = self.replicator.beat().await;
+                },
                 Some(msg) = self.replicator.rx_repl.recv() => { 
                     let _ = self.handle_replication_msg(msg).await;
                 }
@@ -445,28 +1088,7 @@ impl<'a, T: ClientData, R: Tracker<Entity=T>> UpToDate<'a, T, R> {
                 if self.replicator.match_index >= index {
                     return Ok(());
                 }
-                let response = match self.replicator.append_entry(index).await {
-                    Ok(resp) => resp,
-                    Err(_) => {
-                        info!("Did not respond Replicator switching to Lagged");
-                        self.replicator.state = ReplicationState::Lagged;
-                        return Ok(());
-                    }
-                };
-                info!("Response is {:?}", response);
-                if !response.success {
-                    self.replicator.state = ReplicationState::Lagged;
-                    return Ok(());
-                }
-                self.replicator.match_index = self.replicator.next_index;
-                self.replicator.next_index += 1;
-
-                self.replicator.tx_repl.send(ReplicatorMsg::ReplicateResp {
-                    match_index: self.replicator.match_index,
-                    next_index: self.replicator.next_index,
-                    id: self.replicator.node.id.clone()
-                })?;
-
+                self.replicator.state = ReplicationState::Updating;
             },
             _ => unreachable!()
         }
@@ -489,38 +1111,56 @@ impl<'a, T: ClientData, R: Tracker<Entity=T>> NeedSnappshot<'a, T, R> {
     pub async fn run(&mut self) {
         let mut backoff = Duration::from_millis(1);
         let tracker = self.replicator.tracker.read().await;
+        let snapshot_no = tracker.get_snapshot_no();
+        let last_included_index = tracker.get_last_snapshot_index();
+        let last_included_term = tracker.get_last_snapshot_term();
+        drop(tracker);
 
-        let data = match tracker.read_snapshot().await {
-            Ok(data) => data,
-            Err(err) => {
-                self.replicator.state = ReplicationState::Lagged;
-                error!(cause = %err, "Caused an error: ");
+        let mut offset = 0;
+        loop {
+            let tracker = self.replicator.tracker.read().await;
+            // A newer snapshot replaced the one being sent, start over with it.
+            if tracker.get_snapshot_no() != snapshot_no {
                 return;
             }
-        };
-
-        let request = SnapshotRequest { 
-            term: self.replicator.term,
-            leader_id: self.replicator.id.to_string(),
-            last_included_index: tracker.get_last_snapshot_index(),
-            last_included_term: tracker.get_last_snapshot_term(),
-            offset: tracker.get_snapshot_no(),
-            data,
-            done: true
-        };
+            let (data, done) = match tracker.read_snapshot(offset, self.replicator.limits.snapshot_chunk).await {
+                Ok(chunk) => chunk,
+                Err(err) => {
+                    self.replicator.state = ReplicationState::Lagged;
+                    error!(cause = %err, "Caused an error: ");
+                    return;
+                }
+            };
+            drop(tracker);
 
-        drop(tracker);
+            let request = SnapshotRequest {
+                term: self.replicator.term,
+                leader_id: self.replicator.id.to_string(),
+                last_included_index,
+                last_included_term,
+                offset,
+                data,
+                done,
+                nodes: self.replicator.rx_members.borrow().0.clone(),
+                learners: self.replicator.rx_members.borrow().1.clone(),
+                snapshot_no
+            };
 
-        loop {
-            let node = self.replicator.get_node();
-            match rpc::install_snapshot(&node, request.clone()).await {
+            match self.replicator.send_snapshot(request).await {
+                Ok(resp) if resp.term > self.replicator.term => {
+                    sleep(self.replicator.heartbeat).await;
+                },
                 Ok(resp) => {
-                    info!("snapshot responsed with {:?}", resp);
-                    let tracker = self.replicator.tracker.read().await;
-                    self.replicator.match_index = tracker.get_last_snapshot_index();
-                    self.replicator.next_index = tracker.get_last_snapshot_index() + 1;
-                    self.replicator.state = ReplicationState::Lagged;
-                    break;
+                    info!("snapshot chunk at {} responsed with {:?}", offset, resp);
+                    backoff = Duration::from_millis(1);
+                    if resp.done {
+                        self.replicator.match_index = last_included_index;
+                        self.replicator.next_index = last_included_index + 1;
+                        self.replicator.state = ReplicationState::Lagged;
+                        break;
+                    }
+                    // Resume from whatever the follower has staged so far.
+                    offset = resp.offset;
                 },
                 Err(err) => {
                     error!(cause = %err, "Caused an error: ");
@@ -529,8 +1169,8 @@ impl<'a, T: ClientData, R: Tracker<Entity=T>> NeedSnappshot<'a, T, R> {
                     continue;
                 }
             }
-
         }
     }
 
+
 }
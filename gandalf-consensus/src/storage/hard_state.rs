@@ -0,0 +1,59 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use serde::{Serialize, Deserialize};
+
+use tracing::info;
+
+use crate::NodeID;
+
+const HARD_STATE_FILE: &str = "hard_state";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HardState {
+    pub current_term: u64,
+    pub voted_for: Option<NodeID>,
+    #[serde(default)]
+    pub nodes: Option<Vec<NodeID>>,
+    #[serde(default)]
+    pub learners: Vec<NodeID>
+}
+
+#[derive(Debug)]
+pub struct HardStateStore {
+    dir: PathBuf
+}
+
+impl HardStateStore {
+    pub fn open(dir: impl AsRef<Path>) -> crate::Result<(HardStateStore, HardState)> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let path = dir.join(HARD_STATE_FILE);
+        let state = if path.exists() {
+            let file = File::open(&path)?;
+            serde_json::from_reader(file)?
+        } else {
+            HardState::default()
+        };
+        info!("Loaded hard state {:?}", state);
+
+        Ok((HardStateStore { dir }, state))
+    }
+
+    pub fn save(&self, state: &HardState) -> crate::Result<()> {
+        let tmp = self.dir.join(format!("{}.tmp", HARD_STATE_FILE));
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&tmp)?;
+        file.write_all(&serde_json::to_vec(state)?)?;
+        file.sync_all()?;
+
+        fs::rename(&tmp, self.dir.join(HARD_STATE_FILE))?;
+        File::open(&self.dir)?.sync_all()?;
+        Ok(())
+    }
+}
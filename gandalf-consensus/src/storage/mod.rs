@@ -0,0 +1,5 @@
+pub mod wal;
+pub use wal::{Wal, FsyncPolicy};
+
+pub mod hard_state;
+pub use hard_state::{HardState, HardStateStore};
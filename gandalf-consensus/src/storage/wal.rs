@@ -0,0 +1,296 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::marker::PhantomData;
+use std::str::FromStr;
+
+use bytes::{Buf, BufMut, BytesMut};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use tracing::{info, error};
+
+use crate::tracker::{Index, Term};
+
+pub const SEGMENT_SIZE: u64 = 64 * 1024 * 1024;
+
+// Every record is laid out as [payload len: u32][crc32: u32][index: u64][term: u64][json payload],
+// the checksum covering everything after itself.
+const HEADER_SIZE: usize = 24;
+
+const SEGMENT_EXT: &str = "wal";
+
+pub type Record<T> = (Index, Term, T);
+
+type Offset = u64;
+
+type Entries<T> = Vec<(Offset, Record<T>)>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsyncPolicy {
+    Always,
+    Every(u64),
+    Never
+}
+
+#[derive(Debug, Clone)]
+struct Segment {
+    first_index: Index,
+    last_index: Index,
+    path: PathBuf
+}
+
+#[derive(Debug)]
+pub struct Wal<T> {
+    dir: PathBuf,
+    segments: Vec<Segment>,
+    active: Option<File>,
+    active_size: u64,
+    fsync: FsyncPolicy,
+    unsynced: u64,
+    entity: PhantomData<T>
+}
+
+impl FromStr for FsyncPolicy {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> crate::Result<FsyncPolicy> {
+        match s {
+            "always" => Ok(FsyncPolicy::Always),
+            "never" => Ok(FsyncPolicy::Never),
+            n => match n.parse::<u64>() {
+                Ok(n) if n > 0 => Ok(FsyncPolicy::Every(n)),
+                _ => Err(format!("Unknown fsync policy {}", s).into())
+            }
+        }
+    }
+}
+
+impl<T: Serialize + DeserializeOwned> Wal<T> {
+    pub fn open(dir: impl AsRef<Path>, fsync: FsyncPolicy) -> crate::Result<(Wal<T>, Vec<Record<T>>)> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let mut paths = Vec::new();
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some(SEGMENT_EXT) {
+                continue;
+            }
+            let first_index = path.file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.parse::<Index>().ok());
+            if let Some(first_index) = first_index {
+                paths.push((first_index, path));
+            }
+        }
+        paths.sort();
+
+        let count = paths.len();
+        let mut segments = Vec::new();
+        let mut records: Vec<Record<T>> = Vec::new();
+
+        for (i, (first_index, path)) in paths.into_iter().enumerate() {
+            let (entries, valid) = read_segment::<T>(&path)?;
+            if valid < fs::metadata(&path)?.len() {
+                if i + 1 != count {
+                    return Err(format!("Corrupted log segment {:?}", path).into());
+                }
+                error!("Truncating torn tail of {:?} at offset {}", path, valid);
+                OpenOptions::new().write(true).open(&path)?.set_len(valid)?;
+            }
+
+            let mut expected = match records.last() {
+                Some((index, _, _)) => index + 1,
+                None => first_index
+            };
+            if first_index != expected {
+                return Err(format!("Log segment {:?} is not contiguous", path).into());
+            }
+            for (_, record) in entries.into_iter() {
+                if record.0 != expected {
+                    return Err(format!("Log segment {:?} is not contiguous", path).into());
+                }
+                expected += 1;
+                records.push(record);
+            }
+
+            segments.push(Segment { first_index, last_index: expected - 1, path });
+        }
+
+        info!("Loaded {} log entries from {} segments", records.len(), segments.len());
+
+        let mut wal = Wal {
+            dir,
+            segments,
+            active: None,
+            active_size: 0,
+            fsync,
+            unsynced: 0,
+            entity: PhantomData
+        };
+        wal.reopen()?;
+
+        Ok((wal, records))
+    }
+
+    pub fn append(&mut self, index: Index, term: Term, entity: &T) -> crate::Result<()> {
+        if let Some(segment) = self.segments.last() {
+            if segment.last_index + 1 != index {
+                return Err(format!("Log index {} is not contiguous with {}",
+                    index, segment.last_index).into());
+            }
+        }
+        if self.active.is_none() || self.active_size >= SEGMENT_SIZE {
+            self.roll(index)?;
+        }
+
+        let buf = encode(index, term, entity)?;
+        if let Some(file) = &mut self.active {
+            file.write_all(&buf)?;
+        }
+        self.active_size += buf.len() as u64;
+        if let Some(segment) = self.segments.last_mut() {
+            segment.last_index = index;
+        }
+
+        self.unsynced += 1;
+        match self.fsync {
+            FsyncPolicy::Always => self.sync()?,
+            FsyncPolicy::Every(n) if self.unsynced >= n => self.sync()?,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    pub fn truncate(&mut self, from: Index) -> crate::Result<()> {
+        self.active = None;
+        while let Some(segment) = self.segments.last() {
+            if segment.first_index < from {
+                break;
+            }
+            fs::remove_file(&segment.path)?;
+            self.segments.pop();
+        }
+
+        if let Some(segment) = self.segments.last_mut() {
+            if segment.last_index >= from {
+                let (entries, _) = read_segment::<T>(&segment.path)?;
+                let offset = entries.iter()
+                    .find(|(_, record)| record.0 == from)
+                    .map(|(offset, _)| *offset)
+                    .ok_or("Could not find the truncation point")?;
+                let file = OpenOptions::new().write(true).open(&segment.path)?;
+                file.set_len(offset)?;
+                file.sync_data()?;
+                segment.last_index = from - 1;
+            }
+        }
+
+        self.unsynced = 0;
+        self.reopen()
+    }
+
+    pub fn compact(&mut self, upto: Index) -> crate::Result<()> {
+        while !self.segments.is_empty() && self.segments[0].last_index <= upto {
+            let segment = self.segments.remove(0);
+            fs::remove_file(&segment.path)?;
+            if self.segments.is_empty() {
+                self.active = None;
+                self.active_size = 0;
+                self.unsynced = 0;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn reset(&mut self) -> crate::Result<()> {
+        self.active = None;
+        self.active_size = 0;
+        self.unsynced = 0;
+        for segment in self.segments.drain(..) {
+            fs::remove_file(&segment.path)?;
+        }
+        Ok(())
+    }
+
+    pub fn sync(&mut self) -> crate::Result<()> {
+        if let Some(file) = &self.active {
+            if self.unsynced > 0 {
+                file.sync_data()?;
+            }
+        }
+        self.unsynced = 0;
+        Ok(())
+    }
+
+    fn roll(&mut self, first_index: Index) -> crate::Result<()> {
+        self.sync()?;
+        let path = self.dir.join(format!("{:020}.{}", first_index, SEGMENT_EXT));
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)?;
+        if self.fsync != FsyncPolicy::Never {
+            File::open(&self.dir)?.sync_all()?;
+        }
+        self.segments.push(Segment { first_index, last_index: first_index - 1, path });
+        self.active = Some(file);
+        self.active_size = 0;
+        Ok(())
+    }
+
+    fn reopen(&mut self) -> crate::Result<()> {
+        self.active = None;
+        self.active_size = 0;
+        if let Some(segment) = self.segments.last() {
+            let file = OpenOptions::new().append(true).open(&segment.path)?;
+            self.active_size = file.metadata()?.len();
+            self.active = Some(file);
+        }
+        Ok(())
+    }
+}
+
+fn encode<T: Serialize>(index: Index, term: Term, entity: &T) -> crate::Result<BytesMut> {
+    let payload = serde_json::to_vec(entity)?;
+    let mut buf = BytesMut::with_capacity(HEADER_SIZE + payload.len());
+    buf.put_u32(payload.len() as u32);
+    buf.put_u32(0);
+    buf.put_u64(index);
+    buf.put_u64(term);
+    buf.put(&payload[..]);
+    let crc = crc32fast::hash(&buf[8..]);
+    buf[4..8].copy_from_slice(&crc.to_be_bytes());
+    Ok(buf)
+}
+
+fn read_segment<T: DeserializeOwned>(path: &Path) -> crate::Result<(Entries<T>, Offset)> {
+    let data = fs::read(path)?;
+    let mut buf = &data[..];
+    let mut entries = Vec::new();
+    let mut offset = 0;
+
+    while buf.remaining() >= HEADER_SIZE {
+        let len = (&buf[..4]).get_u32() as usize;
+        if buf.remaining() < HEADER_SIZE + len {
+            break;
+        }
+        // A record that was only partly written before a crash fails the checksum.
+        if (&buf[4..8]).get_u32() != crc32fast::hash(&buf[8..HEADER_SIZE + len]) {
+            break;
+        }
+        buf.advance(8);
+        let index = buf.get_u64();
+        let term = buf.get_u64();
+        let entity = match serde_json::from_slice(&buf[..len]) {
+            Ok(entity) => entity,
+            Err(_) => break
+        };
+        buf.advance(len);
+        entries.push((offset, (index, term, entity)));
+        offset += (HEADER_SIZE + len) as u64;
+    }
+
+    Ok((entries, offset))
+}
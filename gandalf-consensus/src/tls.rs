@@ -0,0 +1,288 @@
+use rustls::{Certificate, PrivateKey, RootCertStore, TLSError, DistinguishedNames};
+use rustls::{ClientConfig, ServerConfig, ClientHello, SignatureScheme};
+use rustls::{ClientCertVerifier, ClientCertVerified, ServerCertVerifier, ServerCertVerified};
+use rustls::{ResolvesClientCert, ResolvesServerCert, NoClientSessionStorage, NoServerSessionStorage};
+use rustls::NoClientAuth;
+use rustls::sign::{self, CertifiedKey};
+use rustls::internal::pemfile;
+
+use tonic::transport::{self, ClientTlsConfig, ServerTlsConfig};
+
+use tokio_rustls::TlsAcceptor;
+
+use tokio::time::{self, Duration};
+
+use x509_parser::extensions::GeneralName;
+
+use std::collections::HashSet;
+use std::convert::TryInto;
+use std::fmt;
+use std::fs::File;
+use std::io::BufReader;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::{Arc, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tracing::{info, error};
+
+const ALPN_H2: &[u8] = b"h2";
+
+const RELOAD_INTERVAL: Duration = Duration::from_secs(1);
+
+static SIG_ALGS: &[&webpki::SignatureAlgorithm] = &[
+    &webpki::ECDSA_P256_SHA256,
+    &webpki::ECDSA_P256_SHA384,
+    &webpki::ECDSA_P384_SHA256,
+    &webpki::ECDSA_P384_SHA384,
+    &webpki::ED25519,
+    &webpki::RSA_PSS_2048_8192_SHA256_LEGACY_KEY,
+    &webpki::RSA_PSS_2048_8192_SHA384_LEGACY_KEY,
+    &webpki::RSA_PSS_2048_8192_SHA512_LEGACY_KEY,
+    &webpki::RSA_PKCS1_2048_8192_SHA256,
+    &webpki::RSA_PKCS1_2048_8192_SHA384,
+    &webpki::RSA_PKCS1_2048_8192_SHA512,
+    &webpki::RSA_PKCS1_3072_8192_SHA384
+];
+
+// Peers are dialed by ip, so certificates are matched on their ip SANs
+// instead of a dns name.
+#[derive(Clone)]
+pub struct PeerTls {
+    ca: String,
+    cert: String,
+    key: String,
+    identity: Arc<RwLock<Identity>>,
+    members: Arc<RwLock<HashSet<IpAddr>>>
+}
+
+struct Identity {
+    roots: RootCertStore,
+    certified: CertifiedKey,
+    modified: Vec<SystemTime>
+}
+
+struct MemberVerifier(PeerTls);
+
+struct PeerVerifier {
+    tls: PeerTls,
+    ip: IpAddr
+}
+
+struct CertResolver(PeerTls);
+
+impl PeerTls {
+    pub fn load(ca: String, cert: String, key: String) -> crate::Result<PeerTls> {
+        let identity = Identity::load(&ca, &cert, &key)?;
+        Ok(PeerTls {
+            ca,
+            cert,
+            key,
+            identity: Arc::new(RwLock::new(identity)),
+            members: Arc::new(RwLock::new(HashSet::new()))
+        })
+    }
+
+    pub fn set_members(&self, members: HashSet<IpAddr>) {
+        *self.members.write().unwrap() = members;
+    }
+
+    pub fn server_config(&self) -> ServerTlsConfig {
+        let mut config = ServerConfig::new(Arc::new(MemberVerifier(self.clone())));
+        config.cert_resolver = Arc::new(CertResolver(self.clone()));
+        // Resumed sessions would skip the membership check.
+        config.session_storage = Arc::new(NoServerSessionStorage {});
+        config.set_protocols(&[ALPN_H2.to_vec()]);
+        let mut tls = ServerTlsConfig::new();
+        tls.rustls_server_config(config);
+        tls
+    }
+
+    pub fn client_config(&self, ip: IpAddr) -> ClientTlsConfig {
+        let mut config = ClientConfig::new();
+        config.dangerous().set_certificate_verifier(Arc::new(PeerVerifier { tls: self.clone(), ip }));
+        config.client_auth_cert_resolver = Arc::new(CertResolver(self.clone()));
+        config.session_persistence = Arc::new(NoClientSessionStorage {});
+        config.enable_tickets = false;
+        config.set_protocols(&[ALPN_H2.to_vec()]);
+        // Only used for SNI, the verifier ignores it.
+        ClientTlsConfig::new().rustls_client_config(config).domain_name("gandalf")
+    }
+
+    pub fn reload(&self) -> crate::Result<bool> {
+        let modified = modified(&[&self.ca, &self.cert, &self.key])?;
+        if self.identity.read().unwrap().modified == modified {
+            return Ok(false);
+        }
+        let identity = Identity::load(&self.ca, &self.cert, &self.key)?;
+        *self.identity.write().unwrap() = identity;
+        info!("Reloaded peer certificates from {}", self.cert);
+        Ok(true)
+    }
+
+    pub async fn watch(self) {
+        let mut interval = time::interval(RELOAD_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(err) = self.reload() {
+                error!(cause = %err, "Failed to reload peer certificates");
+            }
+        }
+    }
+
+    fn verify(&self, presented: &[Certificate], server: bool) -> Result<Vec<IpAddr>, TLSError> {
+        let (cert, chain) = presented.split_first().ok_or(TLSError::NoCertificatesPresented)?;
+        let end_entity = webpki::EndEntityCert::from(&cert.0).map_err(TLSError::WebPKIError)?;
+        let chain: Vec<&[u8]> = chain.iter().map(|cert| cert.0.as_ref()).collect();
+        let secs = SystemTime::now().duration_since(UNIX_EPOCH)
+            .map_err(|_| TLSError::FailedToGetCurrentTime)?.as_secs();
+        let now = webpki::Time::from_seconds_since_unix_epoch(secs);
+
+        let identity = self.identity.read().unwrap();
+        let anchors: Vec<webpki::TrustAnchor> = identity.roots.roots.iter()
+            .map(|root| root.to_trust_anchor()).collect();
+        let verified = if server {
+            end_entity.verify_is_valid_tls_server_cert(SIG_ALGS,
+                &webpki::TLSServerTrustAnchors(&anchors), &chain, now)
+        } else {
+            end_entity.verify_is_valid_tls_client_cert(SIG_ALGS,
+                &webpki::TLSClientTrustAnchors(&anchors), &chain, now)
+        };
+        verified.map_err(TLSError::WebPKIError)?;
+        ip_names(cert)
+    }
+}
+
+impl fmt::Debug for PeerTls {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PeerTls").field("ca", &self.ca).field("cert", &self.cert).finish()
+    }
+}
+
+impl Identity {
+    fn load(ca: &str, cert: &str, key: &str) -> crate::Result<Identity> {
+        let modified = modified(&[ca, cert, key])?;
+
+        let mut roots = RootCertStore::empty();
+        let (added, _) = roots.add_pem_file(&mut BufReader::new(File::open(ca)?))
+            .map_err(|_| format!("Invalid CA file {}", ca))?;
+        if added == 0 {
+            return Err(format!("No CA certificates found in {}", ca).into());
+        }
+
+        let certs = load_certs(cert)?;
+        let key = load_key(key)?;
+        let signing_key = sign::any_supported_type(&key)
+            .map_err(|_| "Unsupported private key type")?;
+
+        Ok(Identity {
+            roots,
+            certified: CertifiedKey::new(certs, Arc::new(signing_key)),
+            modified
+        })
+    }
+}
+
+impl ClientCertVerifier for MemberVerifier {
+    fn client_auth_root_subjects(&self, _sni: Option<&webpki::DNSName>) -> Option<DistinguishedNames> {
+        Some(self.0.identity.read().unwrap().roots.get_subjects())
+    }
+
+    fn verify_client_cert(&self, presented_certs: &[Certificate], _sni: Option<&webpki::DNSName>)
+        -> Result<ClientCertVerified, TLSError> {
+        let ips = self.0.verify(presented_certs, false)?;
+        let members = self.0.members.read().unwrap();
+        if !ips.iter().any(|ip| members.contains(ip)) {
+            return Err(TLSError::General(format!("{:?} is not a cluster member", ips)));
+        }
+        Ok(ClientCertVerified::assertion())
+    }
+}
+
+impl ServerCertVerifier for PeerVerifier {
+    fn verify_server_cert(&self, _roots: &RootCertStore, presented_certs: &[Certificate],
+        _dns_name: webpki::DNSNameRef, _ocsp_response: &[u8]) -> Result<ServerCertVerified, TLSError> {
+        let ips = self.tls.verify(presented_certs, true)?;
+        if !ips.contains(&self.ip) {
+            return Err(TLSError::General(format!("Certificate is not valid for {}", self.ip)));
+        }
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<CertifiedKey> {
+        Some(self.0.identity.read().unwrap().certified.clone())
+    }
+}
+
+impl ResolvesClientCert for CertResolver {
+    fn resolve(&self, _acceptable_issuers: &[&[u8]], _sigschemes: &[SignatureScheme])
+        -> Option<CertifiedKey> {
+        Some(self.0.identity.read().unwrap().certified.clone())
+    }
+
+    fn has_certs(&self) -> bool {
+        true
+    }
+}
+
+fn modified(paths: &[&str]) -> crate::Result<Vec<SystemTime>> {
+    let mut modified = Vec::new();
+    for path in paths {
+        modified.push(std::fs::metadata(path)?.modified()?);
+    }
+    Ok(modified)
+}
+
+// Clients are authenticated by the parser, so the client listener only
+// proves its own identity.
+pub fn client_acceptor(cert: &str, key: &str) -> crate::Result<TlsAcceptor> {
+    let mut config = ServerConfig::new(NoClientAuth::new());
+    config.set_single_cert(load_certs(cert)?, load_key(key)?)?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+// The admin endpoint presents the same certificate as the client listener.
+pub fn admin_config(cert: &str, key: &str) -> crate::Result<ServerTlsConfig> {
+    let identity = transport::Identity::from_pem(std::fs::read(cert)?, std::fs::read(key)?);
+    Ok(ServerTlsConfig::new().identity(identity))
+}
+
+fn load_certs(path: &str) -> crate::Result<Vec<Certificate>> {
+    let certs = pemfile::certs(&mut BufReader::new(File::open(path)?))
+        .map_err(|_| format!("Invalid certificate file {}", path))?;
+    if certs.is_empty() {
+        return Err(format!("No certificates found in {}", path).into());
+    }
+    Ok(certs)
+}
+
+fn load_key(path: &str) -> crate::Result<PrivateKey> {
+    let mut keys = pemfile::pkcs8_private_keys(&mut BufReader::new(File::open(path)?))
+        .map_err(|_| format!("Invalid key file {}", path))?;
+    if keys.is_empty() {
+        keys = pemfile::rsa_private_keys(&mut BufReader::new(File::open(path)?))
+            .map_err(|_| format!("Invalid key file {}", path))?;
+    }
+    keys.pop().ok_or_else(|| format!("No private key found in {}", path).into())
+}
+
+fn ip_names(cert: &Certificate) -> Result<Vec<IpAddr>, TLSError> {
+    let (_, cert) = x509_parser::parse_x509_certificate(&cert.0)
+        .map_err(|_| TLSError::WebPKIError(webpki::Error::BadDER))?;
+    let names = match cert.subject_alternative_name() {
+        Ok(Some(names)) => names.value.general_names.clone(),
+        _ => Vec::new()
+    };
+    Ok(names.into_iter().filter_map(|name| match name {
+        GeneralName::IPAddress(ip) if ip.len() == 4 => {
+            let octets: [u8; 4] = ip.try_into().ok()?;
+            Some(IpAddr::V4(Ipv4Addr::from(octets)))
+        },
+        GeneralName::IPAddress(ip) if ip.len() == 16 => {
+            let octets: [u8; 16] = ip.try_into().ok()?;
+            Some(IpAddr::V6(Ipv6Addr::from(octets)))
+        },
+        _ => None
+    }).collect())
+}
@@ -1,6 +1,57 @@
+use serde::{Serialize, Deserialize, de::DeserializeOwned};
+
+use crate::NodeID;
+use crate::parser::Session;
+use crate::raft_rpc::{Entry, EntryType};
+
 pub type Index = u64;
 pub type Term  = u64;
 
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LogEntry<T> {
+    Normal(T),
+    Session {
+        session: Session,
+        entity: T
+    },
+    Noop,
+    Configuration {
+        nodes: Vec<NodeID>,
+        #[serde(default)]
+        learners: Vec<NodeID>
+    }
+}
+
+impl<T: Serialize + DeserializeOwned> LogEntry<T> {
+    pub fn to_entry(&self, term: Term) -> crate::Result<Entry> {
+        let (entry_type, payload) = match self {
+            LogEntry::Normal(entity) => (EntryType::Normal, serde_json::to_string(entity)?),
+            LogEntry::Session{session, entity} =>
+                (EntryType::Session, serde_json::to_string(&(session, entity))?),
+            LogEntry::Noop => (EntryType::Noop, String::new()),
+            LogEntry::Configuration{nodes, learners} =>
+                (EntryType::Configuration, serde_json::to_string(&(nodes, learners))?)
+        };
+        Ok(Entry { payload, term, entry_type: entry_type as i32 })
+    }
+
+    pub fn from_entry(entry: &Entry) -> crate::Result<LogEntry<T>> {
+        match EntryType::from_i32(entry.entry_type) {
+            Some(EntryType::Normal) => Ok(LogEntry::Normal(serde_json::from_str(&entry.payload)?)),
+            Some(EntryType::Session) => {
+                let (session, entity) = serde_json::from_str(&entry.payload)?;
+                Ok(LogEntry::Session{ session, entity })
+            },
+            Some(EntryType::Noop) => Ok(LogEntry::Noop),
+            Some(EntryType::Configuration) => {
+                let (nodes, learners) = serde_json::from_str(&entry.payload)?;
+                Ok(LogEntry::Configuration{ nodes, learners })
+            },
+            None => Err(format!("Unknown entry type {}", entry.entry_type).into())
+        }
+    }
+}
+
 #[tonic::async_trait]
 pub trait Tracker: Sync + Send + Clone + 'static {
     type Entity;
@@ -13,7 +64,7 @@ pub trait Tracker: Sync + Send + Clone + 'static {
 
     fn get_last_commited_index(&self) -> Index;
 
-    fn get_log_entity(&self, index: Index) -> &Self::Entity;
+    fn get_log_entity(&self, index: Index) -> &LogEntry<Self::Entity>;
 
     fn get_log_term(&self, index: Index) -> Term;
 
@@ -23,16 +74,25 @@ pub trait Tracker: Sync + Send + Clone + 'static {
 
     fn get_snapshot_no(&self) -> u64;
 
-    fn append_log(&mut self, entity: Self::Entity, term: Term) -> crate::Result<Index>;
+    fn append_log(&mut self, entity: LogEntry<Self::Entity>, term: Term) -> crate::Result<Index>;
 
     fn delete_last_log(&mut self) -> crate::Result<()>;
 
+    fn truncate_log(&mut self, from: Index) -> crate::Result<()>;
+
     async fn take_snapshot(&mut self) -> crate::Result<()>;
 
-    async fn load_snapshot(&mut self, entity: &Self::Entity, last_log_term: Term, last_log_index: Index, offset: u64)
+    fn stage_snapshot(&mut self, last_log_term: Term, last_log_index: Index, offset: u64, data: &[u8])
+        -> crate::Result<u64>;
+
+    async fn load_snapshot(&mut self, last_log_term: Term, last_log_index: Index, snapshot_no: u64)
         -> crate::Result<()>;
 
-    async fn read_snapshot(&self) -> crate::Result<String>;
+    async fn read_snapshot(&self, offset: u64, len: u64) -> crate::Result<(Vec<u8>, bool)>;
+
+    async fn recover(&mut self) -> crate::Result<()>;
+
+    async fn commit(&mut self, index: Index) -> crate::Result<LogEntry<Self::Entity>>;
 
-    async fn commit(&mut self, index: Index) -> crate::Result<Self::Entity>;
+    fn save_commit_index(&self) -> crate::Result<()>;
 }
@@ -1,19 +1,29 @@
 use gandalf_consensus::{Raft, ConfigMap, ClientData, Tracker};
-use gandalf_consensus::server::Listener;
+use gandalf_consensus::raft_rpc::admin_client::AdminClient;
+use gandalf_consensus::server::{self, Listener};
 use gandalf_consensus::parser::Parser;
-use gandalf_consensus::rpc::RaftRpcService;
-use gandalf_consensus::raft_rpc::raft_rpc_server::RaftRpcServer;
 
 use tokio::net::TcpListener;
 use tokio::sync::{mpsc, RwLock};
+use tokio::time::Duration;
 
-use tonic::transport::Server;
+use tonic::{Request, Status};
+use tonic::codegen::InterceptedService;
+use tonic::service::Interceptor;
+use tonic::transport::{Channel, Endpoint};
 
 use std::sync::Arc;
 use std::net::SocketAddr;
 
 use std::cell::RefCell;
 
+pub const ADMIN_TOKEN: &str = "mellon";
+
+// Admin listens 1000 ports above the raft rpc of a node.
+pub const ADMIN_PORT_OFFSET: u16 = 1000;
+
+pub type Admin = AdminClient<InterceptedService<Channel, WithToken>>;
+
 pub async fn create_cluster<T: ClientData, R: Tracker<Entity=T>, P: Parser<T>>
 (node_configs: Vec<NodeConfig>, tracker: Vec<R>, parser: P)
     -> gandalf_consensus::Result<Vec<(RefCell<Raft<T, R>>, SocketAddr)>> {
@@ -26,14 +36,17 @@ pub async fn create_cluster<T: ClientData, R: Tracker<Entity=T>, P: Parser<T>>
     Ok(cluster)
 }
 
-pub async fn create_node<T: ClientData, R: Tracker<Entity=T>, P: Parser<T>>(conf: NodeConfig, tracker: R, parser: P) 
+pub async fn create_node<T: ClientData, R: Tracker<Entity=T>, P: Parser<T>>(conf: NodeConfig, mut tracker: R, parser: P) 
     -> gandalf_consensus::Result<Raft<T, R>> {
+    tracker.recover().await?;
+
     let (tx_rpc, rx_rpc) = mpsc::unbounded_channel();
 
     let nodes = conf.nodes.ok_or("You must pass list of nodes")?;
 
-    let config = ConfigMap::new(conf.host, conf.port, nodes, conf.heartbeat,
+    let mut config = ConfigMap::new(conf.host, conf.port, nodes, conf.heartbeat,
         conf.timeout, conf.connection_host, conf.connection_port, conf.snapshot_offset)?;
+    config.snapshot_path = conf.snapshot_path;
 
     let id = format!("{}:{}", config.host, config.port);
     let addr = format!("{}:{}", config.host, config.port).parse()?;
@@ -41,22 +54,49 @@ pub async fn create_node<T: ClientData, R: Tracker<Entity=T>, P: Parser<T>>(conf
     let tcp_listener = TcpListener::bind(&format!("{}:{}",
             config.connecntion_host, config.connecntion_port)).await?;
 
-    let mut listener = Listener::new(tcp_listener, tx_rpc.clone());
+    let mut listener = Listener::new(tcp_listener, tx_rpc.clone(),
+        Duration::from_millis(config.client_timeout));
+
+    let raft = Raft::new(config, rx_rpc, Arc::new(RwLock::new(tracker)), id)?;
+    listener.set_metrics(raft.metrics.clone());
 
     tokio::spawn(async move {
             let _ = listener.run(parser).await;
         }
     );
 
-    let raft_rpc = RaftRpcService::<T>::new(tx_rpc.clone());
-    let svc = RaftRpcServer::new(raft_rpc);
+    let mut admin_addr: SocketAddr = addr;
+    admin_addr.set_port(addr.port() + ADMIN_PORT_OFFSET);
+    let tx_admin = tx_rpc.clone();
+    tokio::spawn(async move {
+            let _ = server::serve_admin::<T>(tx_admin, admin_addr, ADMIN_TOKEN.to_string(), None).await;
+        }
+    );
 
     tokio::spawn(async move {
-            let _ = Server::builder().add_service(svc).serve(addr).await;
+            let _ = server::serve_rpc::<T>(tx_rpc, addr, None).await;
         }
     );
 
-    Ok(Raft::new(config, rx_rpc, Arc::new(RwLock::new(tracker)), id))
+    Ok(raft)
+}
+
+#[derive(Clone)]
+pub struct WithToken;
+
+impl Interceptor for WithToken {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        let value = format!("Bearer {}", ADMIN_TOKEN).parse().map_err(|_| Status::internal("Bad token"))?;
+        request.metadata_mut().insert("authorization", value);
+        Ok(request)
+    }
+}
+
+pub async fn admin_client(id: &str) -> gandalf_consensus::Result<Admin> {
+    let mut addr: SocketAddr = id.parse()?;
+    addr.set_port(addr.port() + ADMIN_PORT_OFFSET);
+    let channel = Endpoint::from_shared(format!("http://{}", addr))?.connect().await?;
+    Ok(AdminClient::with_interceptor(channel, WithToken))
 }
 
 pub async fn create_kvs_server() -> SocketAddr {
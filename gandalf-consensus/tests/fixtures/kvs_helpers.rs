@@ -1,11 +1,14 @@
 use gandalf_consensus::client::kvs::{KvsParser, KvsTracker}; 
-use gandalf_consensus::Raft;
+use gandalf_consensus::{Raft, ConfigMap};
+use gandalf_consensus::storage::FsyncPolicy;
 
 use gandalf_kvs::Frame;
 
 use tokio::time::{Duration, sleep};
+use tokio::sync::{mpsc, RwLock};
 
 use std::net::SocketAddr;
+use std::sync::Arc;
 
 use super::common::{create_kvs_server, NodeConfig, create_cluster};
 
@@ -38,10 +41,10 @@ pub async fn kvs_cluster_of_nth(nth: u16) ->  gandalf_consensus::Result<Vec<(Ref
             client_host: "127.0.0.1".to_string(),
             connection_port: 9876 + i,
             connection_host: "127.0.0.1".to_string(),
-            snapshot_path: std::env::temp_dir().to_str().unwrap().to_string()
+            snapshot_path: snapshot_dir(7900 + i)
         };
         let a = format!("{}:{}", c.client_host, c.client_port).parse()?;
-        let t = KvsTracker::new(a, c.snapshot_path.clone(), c.snapshot_offset);
+        let t = KvsTracker::new(a, c.snapshot_path.clone(), FsyncPolicy::Never)?;
 
         cs.push(c);
         ts.push(t);
@@ -51,6 +54,31 @@ pub async fn kvs_cluster_of_nth(nth: u16) ->  gandalf_consensus::Result<Vec<(Ref
     create_cluster(cs, ts, KvsParser).await
 }
 
+pub fn kvs_raft_node(port: u16, snapshot_path: String) -> gandalf_consensus::Result<Raft<Frame, KvsTracker>> {
+    let (_, rx_rpc) = mpsc::unbounded_channel();
+    let nodes = vec![format!("127.0.0.1:{}", port + 1), format!("127.0.0.1:{}", port + 2)];
+    let mut config = ConfigMap::new("127.0.0.1".to_string(), port, nodes, 500, 1500,
+        "127.0.0.1".to_string(), 9876, 100)?;
+    config.snapshot_path = snapshot_path.clone();
+    let tracker = KvsTracker::new("127.0.0.1:9736".parse()?, snapshot_path, FsyncPolicy::Never)?;
+
+    Raft::new(config, rx_rpc, Arc::new(RwLock::new(tracker)), format!("127.0.0.1:{}", port))
+}
+
+pub fn snapshot_dir(port: u16) -> String {
+    let path = std::env::temp_dir().join(format!("gandalf-{}", port));
+    let _ = std::fs::remove_dir_all(&path);
+    path.to_str().unwrap().to_string()
+}
+
+pub fn set_frame(key: &str, i: u64) -> Frame {
+    Frame::Array(vec![
+        Frame::Simple("set".to_string()),
+        Frame::Simple(format!("{}{}", key, i)),
+        Frame::Bulk(format!("{}", i).into())
+    ])
+}
+
 pub async fn client_write_requset(count: u32, addr: String, sleep_duration: Duration) -> gandalf_consensus::Result<()> {
     sleep(sleep_duration).await;
     let mut con = client::connect(addr).await?;
@@ -0,0 +1,96 @@
+mod fixtures;
+
+use gandalf_consensus::raft::State;
+use gandalf_consensus::raft_rpc::admin_client::AdminClient;
+use gandalf_consensus::raft_rpc::{StatusRequest, TakeSnapshotRequest, TransferLeadershipRequest};
+
+use tokio::time::{Duration, sleep};
+
+use tonic::Code;
+
+use fixtures::common::admin_client;
+use fixtures::kvs_helpers::{client_write_requset, kvs_cluster_of_nth};
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 5)]
+async fn test_admin_service() -> gandalf_consensus::Result<()> {
+    let cluster = kvs_cluster_of_nth(3).await?;
+
+    let mut node1 = cluster.get(0).unwrap().0.borrow_mut();
+    let mut node2 = cluster.get(1).unwrap().0.borrow_mut();
+    let mut node3 = cluster.get(2).unwrap().0.borrow_mut();
+
+    node1.current_term = 1;
+    node1.set_state(State::Leader);
+
+    node2.current_term = 1;
+    node2.current_leader = Some(node1.id.clone());
+
+    node3.current_term = 1;
+    node3.current_leader = Some(node1.id.clone());
+
+    let connection_addr = format!("127.0.0.1:{}", 9876).to_string();
+
+    tokio::select! {
+        _ = node1.run() => {
+            assert!(false);
+        },
+        _ = node2.run()  => {
+            assert!(false);
+        },
+        _ = node3.run()  => {
+            assert!(false);
+        },
+        res = async {
+            client_write_requset(10, connection_addr, Duration::from_secs(0)).await?;
+            sleep(Duration::from_millis(500)).await;
+
+            // Without the token, and never on the peer port.
+            let mut anonymous = AdminClient::connect("http://127.0.0.1:8900").await?;
+            let err = anonymous.status(StatusRequest {}).await.unwrap_err();
+            assert_eq!(err.code(), Code::Unauthenticated);
+            let mut peer_port = AdminClient::connect("http://127.0.0.1:7900").await?;
+            let err = peer_port.status(StatusRequest {}).await.unwrap_err();
+            assert_eq!(err.code(), Code::Unimplemented);
+
+            let mut leader = admin_client("127.0.0.1:7900").await?;
+            let status = leader.status(StatusRequest {}).await?.into_inner();
+            assert_eq!(status.id, "127.0.0.1:7900");
+            assert_eq!(status.state, "Leader");
+            assert_eq!(status.term, 1);
+            assert_eq!(status.leader_id, "127.0.0.1:7900");
+            assert!(status.commit_index >= 10);
+            assert_eq!(status.peers.len(), 2);
+            for peer in status.peers.iter() {
+                assert_eq!(peer.match_index, status.last_log_index);
+                assert!(peer.connected);
+            }
+
+            let snapshot = leader.take_snapshot(TakeSnapshotRequest {}).await?.into_inner();
+            assert_eq!(snapshot.snapshot_no, status.snapshot_no + 1);
+            assert_eq!(snapshot.last_included_index, status.commit_index);
+
+            let mut follower = admin_client("127.0.0.1:7901").await?;
+            let status = follower.status(StatusRequest {}).await?.into_inner();
+            assert_eq!(status.state, "Follower");
+            assert_eq!(status.leader_id, "127.0.0.1:7900");
+
+            let response = leader.transfer_leadership(TransferLeadershipRequest {
+                node_id: "127.0.0.1:7901".to_string()
+            }).await?.into_inner();
+            assert!(response.success);
+            sleep(Duration::from_secs(1)).await;
+            Ok::<_, gandalf_consensus::Error>(())
+        } => {
+            res?
+        }
+    }
+
+    assert_eq!(node2.state, State::Leader);
+    assert_eq!(node1.state, State::Follower);
+
+    drop(node1);
+    drop(node2);
+    drop(node3);
+
+    Ok(())
+}
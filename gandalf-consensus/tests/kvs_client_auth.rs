@@ -0,0 +1,108 @@
+mod fixtures;
+
+use gandalf_consensus::RaftMessage;
+use gandalf_consensus::client::kvs::KvsParser;
+use gandalf_consensus::server::Listener;
+use gandalf_consensus::tls::client_acceptor;
+
+use gandalf_kvs::{Connection, Frame};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::time::{Duration, timeout};
+
+use tokio_rustls::TlsConnector;
+use tokio_rustls::rustls::ClientConfig;
+use tokio_rustls::webpki::DNSNameRef;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use fixtures::kvs_helpers::snapshot_dir;
+
+const GET_FOO: &[u8] = b"*2\r\n$3\r\nget\r\n$3\r\nfoo\r\n";
+
+fn command(parts: &[&str]) -> Frame {
+    Frame::Array(parts.iter().map(|part| Frame::Bulk(part.to_string().into())).collect())
+}
+
+async fn answer_read(rx: &mut mpsc::UnboundedReceiver<RaftMessage<Frame>>) {
+    match rx.recv().await {
+        Some(RaftMessage::ClientReadMsg{tx, ..}) => {
+            let _ = tx.send(RaftMessage::ClientResp { body: Frame::Bulk("bar".into()) });
+        },
+        msg => panic!("Unexpected message {:?}", msg)
+    }
+}
+
+#[tokio::test]
+async fn test_requests_rejected_before_auth() -> gandalf_consensus::Result<()> {
+    let (tx_rpc, mut rx_rpc) = mpsc::unbounded_channel();
+    let mut listener = Listener::<KvsParser, Frame>::new(TcpListener::bind("127.0.0.1:7964").await?,
+        tx_rpc, Duration::from_secs(1));
+    let mut users = HashMap::new();
+    users.insert("gandalf".to_string(), "mellon".to_string());
+    listener.set_users(users);
+    tokio::spawn(async move { listener.run(KvsParser).await });
+
+    let mut con = Connection::new(TcpStream::connect("127.0.0.1:7964").await?);
+
+    con.write_frame(&command(&["get", "foo"])).await?;
+    assert!(matches!(con.read().await?, Some(Frame::Error(err)) if err.starts_with("NOAUTH")));
+    assert!(rx_rpc.try_recv().is_err());
+
+    con.write_frame(&command(&["AUTH", "gandalf", "mellon", "friend"])).await?;
+    assert!(matches!(con.read().await?, Some(Frame::Error(err)) if err.starts_with("ERR")));
+
+    con.write_frame(&command(&["AUTH", "gandalf", "friend"])).await?;
+    assert!(matches!(con.read().await?, Some(Frame::Error(err)) if err.starts_with("WRONGPASS")));
+
+    con.write_frame(&command(&["AUTH", "gandalf", "mellon"])).await?;
+    assert!(matches!(con.read().await?, Some(Frame::Simple(ok)) if ok == "OK"));
+
+    con.write_frame(&command(&["CLIENT", "TIMEOUT", "soon"])).await?;
+    assert!(matches!(con.read().await?, Some(Frame::Error(err)) if err.starts_with("ERR")));
+
+    con.write_frame(&command(&["get", "foo"])).await?;
+    answer_read(&mut rx_rpc).await;
+    assert!(matches!(con.read().await?, Some(Frame::Bulk(value)) if value == "bar"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_client_listener_over_tls() -> gandalf_consensus::Result<()> {
+    let dir = snapshot_dir(7965);
+    std::fs::create_dir_all(&dir)?;
+    let key = rcgen::KeyPair::generate()?;
+    let cert = rcgen::CertificateParams::new(vec!["localhost".to_string()])?.self_signed(&key)?;
+    std::fs::write(format!("{}/client.crt", dir), cert.pem())?;
+    std::fs::write(format!("{}/client.key", dir), key.serialize_pem())?;
+
+    let (tx_rpc, mut rx_rpc) = mpsc::unbounded_channel();
+    let mut listener = Listener::<KvsParser, Frame>::new(TcpListener::bind("127.0.0.1:7965").await?,
+        tx_rpc, Duration::from_secs(1));
+    listener.set_tls(client_acceptor(&format!("{}/client.crt", dir), &format!("{}/client.key", dir))?);
+    tokio::spawn(async move { listener.run(KvsParser).await });
+
+    // A plaintext client never gets through the handshake.
+    let mut plain = TcpStream::connect("127.0.0.1:7965").await?;
+    plain.write_all(GET_FOO).await?;
+    let mut buf = vec![0; 64];
+    assert!(matches!(timeout(Duration::from_secs(1), plain.read(&mut buf)).await?, Ok(0) | Err(_)));
+    assert!(rx_rpc.try_recv().is_err());
+
+    let mut config = ClientConfig::new();
+    config.root_store.add_pem_file(&mut cert.pem().as_bytes()).unwrap();
+    let connector = TlsConnector::from(Arc::new(config));
+    let mut stream = connector.connect(DNSNameRef::try_from_ascii_str("localhost")?,
+        TcpStream::connect("127.0.0.1:7965").await?).await?;
+
+    stream.write_all(GET_FOO).await?;
+    answer_read(&mut rx_rpc).await;
+    let n = stream.read(&mut buf).await?;
+    assert_eq!(&buf[..n], b"$3\r\nbar\r\n");
+
+    Ok(())
+}
@@ -1,8 +1,14 @@
 mod fixtures;
 
+use gandalf_consensus::Tracker;
 use gandalf_consensus::raft::State;
+use gandalf_consensus::tracker::LogEntry;
 
-use tokio::time::Duration;
+use gandalf_kvs::{client, Connection, Frame};
+
+use tokio::net::TcpStream;
+
+use tokio::time::{Duration, sleep};
 
 use fixtures::kvs_helpers::{client_write_requset, kvs_cluster_of_nth};
 
@@ -62,7 +68,7 @@ async fn test_direct_write() -> gandalf_consensus::Result<()> {
 
     let _: Vec<_> = cluster.
         iter()
-        .map(|c| assert!(c.0.borrow().get_commit_index() == 10)).collect();
+        .map(|c| assert!(c.0.borrow().get_commit_index() == 11)).collect();
 
     Ok(())
 }
@@ -124,7 +130,190 @@ async fn test_indirect_write() -> gandalf_consensus::Result<()> {
 
     let _: Vec<_> = cluster.
         iter()
-        .map(|c| assert!(c.0.borrow().get_commit_index() == 10)).collect();
+        .map(|c| assert!(c.0.borrow().get_commit_index() == 11)).collect();
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 5)]
+async fn test_pending_write_lost_with_leadership() -> gandalf_consensus::Result<()> {
+    let cluster = kvs_cluster_of_nth(3).await?;
+
+    let mut node1 = cluster.get(0).unwrap().0.borrow_mut();
+    let mut node2 = cluster.get(1).unwrap().0.borrow_mut();
+
+    // node1 can't reach a quorum, so the write stays uncommited until node2
+    // takes over with a log that doesn't hold it.
+    node1.current_term = 1;
+    node1.set_state(State::Leader);
+
+    node2.current_term = 5;
+    node2.set_state(State::Leader);
+
+    let connection_addr = format!("127.0.0.1:{}", 9876).to_string();
+
+    tokio::select! {
+        _ = node1.run() => {
+            assert!(false);
+        },
+        _ = async { sleep(Duration::from_secs(2)).await; node2.run().await }  => {
+            assert!(false);
+        },
+        res = async {
+            let mut con = client::connect(connection_addr).await?;
+            let err = con.set("foo", "bar".into()).await.unwrap_err();
+            // The client address of node2, not its raft rpc one.
+            assert_eq!(err.to_string(), "NOTLEADER 127.0.0.1:9877");
+            Ok::<_, gandalf_consensus::Error>(())
+        } => {
+            res?
+        }
+    }
+
+    assert_eq!(node1.last_term(), 5);
+
+    drop(node1);
+    drop(node2);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 5)]
+async fn test_pending_write_commited_by_next_leader() -> gandalf_consensus::Result<()> {
+    let cluster = kvs_cluster_of_nth(3).await?;
+
+    let mut node1 = cluster.get(0).unwrap().0.borrow_mut();
+    let mut node2 = cluster.get(1).unwrap().0.borrow_mut();
+
+    // node2 already holds the write node1 is about to accept.
+    let set = Frame::Array(vec![
+        Frame::Bulk("set".into()),
+        Frame::Bulk("foo".into()),
+        Frame::Bulk("bar".into())
+    ]);
+    let mut tracker = node2.tracker.write().await;
+    tracker.append_log(LogEntry::Noop, 1)?;
+    tracker.append_log(LogEntry::Normal(set), 1)?;
+    drop(tracker);
+    node2.update_last_log(2, 1);
+
+    node1.current_term = 1;
+    node1.set_state(State::Leader);
+
+    node2.current_term = 5;
+    node2.set_state(State::Leader);
+
+    let connection_addr = format!("127.0.0.1:{}", 9876).to_string();
+
+    tokio::select! {
+        _ = node1.run() => {
+            assert!(false);
+        },
+        _ = async { sleep(Duration::from_secs(2)).await; node2.run().await }  => {
+            assert!(false);
+        },
+        res = async {
+            let mut con = client::connect(connection_addr).await?;
+            con.set("foo", "bar".into()).await?;
+            Ok::<_, gandalf_consensus::Error>(())
+        } => {
+            res?
+        }
+    }
+
+    assert_eq!(node1.get_commit_index(), 3);
+
+    drop(node1);
+    drop(node2);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 5)]
+async fn test_client_write_timeout() -> gandalf_consensus::Result<()> {
+    let cluster = kvs_cluster_of_nth(3).await?;
+
+    let mut node1 = cluster.get(0).unwrap().0.borrow_mut();
+
+    // Without the other nodes the write never reaches a quorum.
+    node1.current_term = 1;
+    node1.set_state(State::Leader);
+
+    let connection_addr = format!("127.0.0.1:{}", 9876).to_string();
+    let command = |parts: &[&str]| Frame::Array(parts.iter()
+        .map(|part| Frame::Bulk(part.to_string().into()))
+        .collect());
+
+    tokio::select! {
+        _ = node1.run() => {
+            assert!(false);
+        },
+        res = async {
+            let mut con = Connection::new(TcpStream::connect(connection_addr).await?);
+            con.write_frame(&command(&["CLIENT", "TIMEOUT", "300"])).await?;
+            assert!(matches!(con.read().await?, Some(Frame::Simple(ok)) if ok == "OK"));
+
+            con.write_frame(&command(&["set", "foo", "bar"])).await?;
+            let resp = tokio::time::timeout(Duration::from_secs(1), con.read()).await??;
+            assert!(matches!(resp, Some(Frame::Error(err)) if err == "TIMEOUT request timed out"));
+            Ok::<_, gandalf_consensus::Error>(())
+        } => {
+            res?
+        }
+    }
+
+    drop(node1);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 5)]
+async fn test_redirect_to_leader() -> gandalf_consensus::Result<()> {
+    let cluster = kvs_cluster_of_nth(3).await?;
+
+    let mut node1 = cluster.get(0).unwrap().0.borrow_mut();
+    let mut node2 = cluster.get(1).unwrap().0.borrow_mut();
+    let mut node3 = cluster.get(2).unwrap().0.borrow_mut();
+
+    node1.current_term = 1;
+    node1.set_state(State::Leader);
+
+    node2.current_term = 1;
+    node2.current_leader = Some(node1.id.clone());
+    node2.redirect = true;
+
+    node3.current_term = 1;
+    node3.current_leader = Some(node1.id.clone());
+
+    tokio::select! {
+        _ = node1.run() => {
+            assert!(false);
+        },
+        _ = node2.run()  => {
+            assert!(false);
+        },
+        _ = node3.run()  => {
+            assert!(false);
+        },
+        res = async {
+            sleep(Duration::from_secs(1)).await;
+            let mut con = client::connect("127.0.0.1:9877").await?;
+            let err = con.set("foo", "bar".into()).await.unwrap_err();
+            assert_eq!(err.to_string(), "MOVED 127.0.0.1:9876");
+            assert!(con.get("foo").await.is_err());
+
+            // Without redirects the request is forwarded as before.
+            let mut con = client::connect("127.0.0.1:9878").await?;
+            con.set("foo", "bar".into()).await?;
+            Ok::<_, gandalf_consensus::Error>(())
+        } => {
+            res?
+        }
+    }
+
+    drop(node1);
+    drop(node2);
+    drop(node3);
 
     Ok(())
 }
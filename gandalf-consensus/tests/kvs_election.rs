@@ -1,10 +1,15 @@
 mod fixtures;
 
+use gandalf_consensus::RaftMessage;
 use gandalf_consensus::raft::State;
+use gandalf_consensus::raft_rpc::{RequestVoteRequest, PreVoteRequest, TransferLeadershipRequest};
+
+use gandalf_kvs::client;
 
 use tokio::time::{Duration, sleep};
 
-use fixtures::kvs_helpers::kvs_cluster_of_nth;
+use fixtures::common::admin_client;
+use fixtures::kvs_helpers::{kvs_cluster_of_nth, kvs_raft_node, snapshot_dir, client_write_requset};
 
 #[tokio::test(flavor = "multi_thread", worker_threads = 5)]
 async fn test_cluster_bootstrap() -> gandalf_consensus::Result<()>{
@@ -204,3 +209,269 @@ async fn test_time_out() -> gandalf_consensus::Result<()> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn test_vote_survives_restart() -> gandalf_consensus::Result<()> {
+    let path = snapshot_dir(7951);
+
+    let mut node = kvs_raft_node(7951, path.clone())?;
+    let request = RequestVoteRequest {
+        term: 3,
+        candidate_id: "127.0.0.1:7952".to_string(),
+        last_log_index: 0,
+        last_log_term: 0
+    };
+    match node.handle_vote_request(request) {
+        RaftMessage::VoteResp { payload, .. } => assert!(payload.vote_granted),
+        _ => panic!("unexpected response")
+    }
+    drop(node);
+
+    let mut node = kvs_raft_node(7951, path)?;
+    assert_eq!(node.current_term, 3);
+    assert_eq!(node.voted_for, Some("127.0.0.1:7952".to_string()));
+
+    let request = RequestVoteRequest {
+        term: 3,
+        candidate_id: "127.0.0.1:7953".to_string(),
+        last_log_index: 0,
+        last_log_term: 0
+    };
+    match node.handle_vote_request(request) {
+        RaftMessage::VoteResp { payload, .. } => assert!(!payload.vote_granted),
+        _ => panic!("unexpected response")
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_vote_for_newer_shorter_log() -> gandalf_consensus::Result<()> {
+    let mut node = kvs_raft_node(7967, snapshot_dir(7967))?;
+    node.update_last_log(5, 1);
+
+    // A newer last term wins even with fewer entries.
+    let request = RequestVoteRequest {
+        term: 3,
+        candidate_id: "127.0.0.1:7968".to_string(),
+        last_log_index: 3,
+        last_log_term: 2
+    };
+    match node.handle_vote_request(request) {
+        RaftMessage::VoteResp { payload, .. } => assert!(payload.vote_granted),
+        _ => panic!("unexpected response")
+    }
+
+    let request = RequestVoteRequest {
+        term: 4,
+        candidate_id: "127.0.0.1:7969".to_string(),
+        last_log_index: 4,
+        last_log_term: 1
+    };
+    match node.handle_vote_request(request) {
+        RaftMessage::VoteResp { payload, .. } => assert!(!payload.vote_granted),
+        _ => panic!("unexpected response")
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_pre_vote_with_live_leader() -> gandalf_consensus::Result<()> {
+    let mut node = kvs_raft_node(7954, snapshot_dir(7954))?;
+    let request = PreVoteRequest {
+        term: 1,
+        candidate_id: "127.0.0.1:7955".to_string(),
+        last_log_index: 0,
+        last_log_term: 0
+    };
+
+    match node.handle_pre_vote_request(request.clone()) {
+        RaftMessage::PreVoteResp { payload, .. } => assert!(payload.vote_granted),
+        _ => panic!("unexpected response")
+    }
+
+    node.heard_from_leader();
+
+    match node.handle_pre_vote_request(request) {
+        RaftMessage::PreVoteResp { payload, .. } => assert!(!payload.vote_granted),
+        _ => panic!("unexpected response")
+    }
+    assert_eq!(node.current_term, 0);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 5)]
+async fn test_check_quorum() -> gandalf_consensus::Result<()> {
+    let cluster = kvs_cluster_of_nth(3).await?;
+
+    let mut node1 = cluster.get(0).unwrap().0.borrow_mut();
+
+    node1.current_term = 1;
+    node1.set_state(State::Leader);
+
+    tokio::select! {
+        _ = node1.run() => {
+            assert!(false);
+        },
+        _ = sleep(Duration::from_secs(4)) => {
+        }
+    }
+
+    assert_ne!(node1.state, State::Leader);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 5)]
+async fn test_transfer_leadership() -> gandalf_consensus::Result<()> {
+    let cluster = kvs_cluster_of_nth(3).await?;
+
+    let mut node1 = cluster.get(0).unwrap().0.borrow_mut();
+    let mut node2 = cluster.get(1).unwrap().0.borrow_mut();
+    let mut node3 = cluster.get(2).unwrap().0.borrow_mut();
+
+    node1.current_term = 1;
+    node1.set_state(State::Leader);
+
+    node2.current_term = 1;
+    node2.current_leader = Some(node1.id.clone());
+
+    node3.current_term = 1;
+    node3.current_leader = Some(node1.id.clone());
+
+    let connection_addr = format!("127.0.0.1:{}", 9876).to_string();
+
+    tokio::select! {
+        _ = node1.run() => {
+            assert!(false);
+        },
+        _ = node2.run()  => {
+            assert!(false);
+        },
+        _ = node3.run()  => {
+            assert!(false);
+        },
+        res = client_write_requset(10, connection_addr, Duration::from_secs(0)) => {
+            res?
+        }
+    }
+
+    let leader = node1.id.clone();
+    let request = TransferLeadershipRequest { node_id: node2.id.clone() };
+
+    tokio::select! {
+        _ = node1.run() => {
+            assert!(false);
+        },
+        _ = node2.run()  => {
+            assert!(false);
+        },
+        _ = node3.run()  => {
+            assert!(false);
+        },
+        res = async {
+            let response = admin_client(&leader).await?.transfer_leadership(request).await?.into_inner();
+            sleep(Duration::from_secs(1)).await;
+            Ok::<_, gandalf_consensus::Error>(response)
+        } => {
+            let response = res?;
+            assert!(response.success);
+            assert_eq!(response.leader_id, node2.id);
+        }
+    }
+
+    assert_eq!(node2.state, State::Leader);
+    assert_eq!(node2.current_term, 2);
+    assert_eq!(node1.state, State::Follower);
+
+    drop(node1);
+    drop(node2);
+    drop(node3);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 5)]
+async fn test_transfer_leadership_with_lease_read() -> gandalf_consensus::Result<()> {
+    let cluster = kvs_cluster_of_nth(3).await?;
+
+    let mut node1 = cluster.get(0).unwrap().0.borrow_mut();
+    let mut node2 = cluster.get(1).unwrap().0.borrow_mut();
+    let mut node3 = cluster.get(2).unwrap().0.borrow_mut();
+
+    node1.current_term = 1;
+    node1.set_state(State::Leader);
+    node1.lease_read = true;
+
+    node2.current_term = 1;
+    node2.current_leader = Some(node1.id.clone());
+
+    node3.current_term = 1;
+    node3.current_leader = Some(node1.id.clone());
+
+    let connection_addr = format!("127.0.0.1:{}", 9876).to_string();
+
+    tokio::select! {
+        _ = node1.run() => {
+            assert!(false);
+        },
+        _ = node2.run()  => {
+            assert!(false);
+        },
+        _ = node3.run()  => {
+            assert!(false);
+        },
+        res = client_write_requset(10, connection_addr.clone(), Duration::from_secs(0)) => {
+            res?
+        }
+    }
+
+    let leader = node1.id.clone();
+    let request = TransferLeadershipRequest { node_id: node2.id.clone() };
+
+    tokio::select! {
+        _ = node1.run() => {
+            assert!(false);
+        },
+        _ = node2.run()  => {
+            assert!(false);
+        },
+        _ = node3.run()  => {
+            assert!(false);
+        },
+        res = async {
+            let mut old_leader = client::connect(connection_addr.clone()).await?;
+            let transfer = tokio::spawn(async move {
+                admin_client(&leader).await?.transfer_leadership(request).await
+                    .map(|response| response.into_inner())
+                    .map_err(gandalf_consensus::Error::from)
+            });
+            // Reads keep going through the old leader while the transfer runs.
+            while !transfer.is_finished() {
+                if let Ok(value) = old_leader.get("foo0").await {
+                    assert_eq!(value, Some("0".into()));
+                }
+            }
+            assert!(transfer.await??.success);
+
+            let mut new_leader = client::connect("127.0.0.1:9877").await?;
+            new_leader.set("foo0", "new".into()).await?;
+            let mut old_leader = client::connect(connection_addr).await?;
+            assert_eq!(old_leader.get("foo0").await?, Some("new".into()));
+            Ok::<_, gandalf_consensus::Error>(())
+        } => {
+            res?
+        }
+    }
+
+    assert_eq!(node2.state, State::Leader);
+    assert_eq!(node1.state, State::Follower);
+
+    drop(node1);
+    drop(node2);
+    drop(node3);
+
+    Ok(())
+}
@@ -0,0 +1,288 @@
+mod fixtures;
+
+use gandalf_consensus::raft::State;
+use gandalf_consensus::raft_rpc::{AddServerRequest, RemoveServerRequest};
+
+use tokio::time::{Duration, sleep};
+
+use fixtures::common::admin_client;
+use fixtures::kvs_helpers::{client_write_requset, kvs_cluster_of_nth};
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 5)]
+async fn test_add_server() -> gandalf_consensus::Result<()> {
+    let cluster = kvs_cluster_of_nth(4).await?;
+
+    let mut node1 = cluster.get(0).unwrap().0.borrow_mut();
+    let mut node2 = cluster.get(1).unwrap().0.borrow_mut();
+    let mut node3 = cluster.get(2).unwrap().0.borrow_mut();
+    let mut node4 = cluster.get(3).unwrap().0.borrow_mut();
+
+    let nodes: Vec<String> = (7900..7903).map(|port| format!("127.0.0.1:{}", port)).collect();
+    node1.apply_configuration(nodes.clone(), vec![])?;
+    node2.apply_configuration(nodes.clone(), vec![])?;
+    node3.apply_configuration(nodes, vec![])?;
+
+    node1.current_term = 1;
+    node1.set_state(State::Leader);
+
+    node2.current_term = 1;
+    node2.current_leader = Some(node1.id.clone());
+
+    node3.current_term = 1;
+    node3.current_leader = Some(node1.id.clone());
+
+    node4.current_term = 1;
+
+    let connection_addr = format!("127.0.0.1:{}", 9876).to_string();
+
+    tokio::select! {
+        _ = node1.run() => {
+            assert!(false);
+        },
+        _ = node2.run()  => {
+            assert!(false);
+        },
+        _ = node3.run()  => {
+            assert!(false);
+        },
+        _ = node4.run()  => {
+            assert!(false);
+        },
+        res = client_write_requset(10, connection_addr, Duration::from_secs(0)) => {
+            res?
+        }
+    }
+
+    let leader = node1.id.clone();
+    let request = AddServerRequest { node_id: node4.id.clone(), learner: false };
+
+    tokio::select! {
+        _ = node1.run() => {
+            assert!(false);
+        },
+        _ = node2.run()  => {
+            assert!(false);
+        },
+        _ = node3.run()  => {
+            assert!(false);
+        },
+        _ = node4.run()  => {
+            assert!(false);
+        },
+        res = async {
+            let response = admin_client(&leader).await?.add_server(request).await?.into_inner();
+            sleep(Duration::from_secs(3)).await;
+            Ok::<_, gandalf_consensus::Error>(response)
+        } => {
+            let response = res?;
+            assert!(response.success);
+            assert_eq!(response.nodes.len(), 4);
+        }
+    }
+
+    assert_eq!(node1.state, State::Leader);
+    assert_eq!(node1.nodes.len(), 3);
+    assert_eq!(node2.nodes.len(), 3);
+    assert_eq!(node3.nodes.len(), 3);
+    assert_eq!(node4.last_index(), node1.last_index());
+    assert_eq!(node4.get_commit_index(), node1.get_commit_index());
+
+    drop(node1);
+    drop(node2);
+    drop(node3);
+    drop(node4);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 5)]
+async fn test_remove_server() -> gandalf_consensus::Result<()> {
+    let cluster = kvs_cluster_of_nth(4).await?;
+
+    let mut node1 = cluster.get(0).unwrap().0.borrow_mut();
+    let mut node2 = cluster.get(1).unwrap().0.borrow_mut();
+    let mut node3 = cluster.get(2).unwrap().0.borrow_mut();
+    let mut node4 = cluster.get(3).unwrap().0.borrow_mut();
+
+    node1.current_term = 1;
+    node1.set_state(State::Leader);
+
+    node2.current_term = 1;
+    node2.current_leader = Some(node1.id.clone());
+
+    node3.current_term = 1;
+    node3.current_leader = Some(node1.id.clone());
+
+    node4.current_term = 1;
+    node4.current_leader = Some(node1.id.clone());
+
+    let connection_addr = format!("127.0.0.1:{}", 9876).to_string();
+
+    tokio::select! {
+        _ = node1.run() => {
+            assert!(false);
+        },
+        _ = node2.run()  => {
+            assert!(false);
+        },
+        _ = node3.run()  => {
+            assert!(false);
+        },
+        _ = node4.run()  => {
+            assert!(false);
+        },
+        res = client_write_requset(10, connection_addr.clone(), Duration::from_secs(0)) => {
+            res?
+        }
+    }
+
+    let leader = node1.id.clone();
+    let request = RemoveServerRequest { node_id: node4.id.clone() };
+
+    tokio::select! {
+        _ = node1.run() => {
+            assert!(false);
+        },
+        _ = node2.run()  => {
+            assert!(false);
+        },
+        _ = node3.run()  => {
+            assert!(false);
+        },
+        _ = node4.run()  => {
+            assert!(false);
+        },
+        res = async {
+            let response = admin_client(&leader).await?.remove_server(request).await?.into_inner();
+            sleep(Duration::from_secs(2)).await;
+            Ok::<_, gandalf_consensus::Error>(response)
+        } => {
+            let response = res?;
+            assert!(response.success);
+            assert_eq!(response.nodes.len(), 3);
+        }
+    }
+
+    assert_eq!(node1.nodes.len(), 2);
+    assert_eq!(node2.nodes.len(), 2);
+    assert_eq!(node3.nodes.len(), 2);
+    assert!(!node1.members().contains(&node4.id));
+
+    tokio::select! {
+        _ = node1.run() => {
+            assert!(false);
+        },
+        _ = node2.run()  => {
+            assert!(false);
+        },
+        _ = node3.run()  => {
+            assert!(false);
+        },
+        res = client_write_requset(10, connection_addr, Duration::from_secs(0)) => {
+            res?
+        }
+    }
+
+    assert_eq!(node1.state, State::Leader);
+    assert_eq!(node2.get_commit_index(), node1.get_commit_index());
+
+    drop(node1);
+    drop(node2);
+    drop(node3);
+    drop(node4);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 5)]
+async fn test_learner() -> gandalf_consensus::Result<()> {
+    let cluster = kvs_cluster_of_nth(4).await?;
+
+    let mut node1 = cluster.get(0).unwrap().0.borrow_mut();
+    let mut node2 = cluster.get(1).unwrap().0.borrow_mut();
+    let mut node3 = cluster.get(2).unwrap().0.borrow_mut();
+    let mut node4 = cluster.get(3).unwrap().0.borrow_mut();
+
+    let nodes: Vec<String> = (7900..7903).map(|port| format!("127.0.0.1:{}", port)).collect();
+    let learners = vec![node4.id.clone()];
+    node1.apply_configuration(nodes.clone(), learners.clone())?;
+    node2.apply_configuration(nodes.clone(), learners.clone())?;
+    node3.apply_configuration(nodes.clone(), learners.clone())?;
+    node4.apply_configuration(nodes, learners)?;
+
+    node1.current_term = 1;
+    node1.set_state(State::Leader);
+
+    node2.current_term = 1;
+    node2.current_leader = Some(node1.id.clone());
+
+    node3.current_term = 1;
+    node3.current_leader = Some(node1.id.clone());
+
+    node4.current_term = 1;
+    node4.current_leader = Some(node1.id.clone());
+
+    assert_eq!(node4.state, State::NonVoter);
+    assert_eq!(node1.nodes.len(), 2);
+
+    let connection_addr = format!("127.0.0.1:{}", 9876).to_string();
+
+    tokio::select! {
+        _ = node1.run() => {
+            assert!(false);
+        },
+        _ = node2.run()  => {
+            assert!(false);
+        },
+        _ = node3.run()  => {
+            assert!(false);
+        },
+        _ = node4.run()  => {
+            assert!(false);
+        },
+        res = client_write_requset(10, connection_addr, Duration::from_secs(0)) => {
+            res?
+        }
+    }
+
+    assert_eq!(node4.state, State::NonVoter);
+    assert_eq!(node4.last_index(), node1.last_index());
+
+    let leader = node1.id.clone();
+    let request = AddServerRequest { node_id: node4.id.clone(), learner: false };
+
+    tokio::select! {
+        _ = node1.run() => {
+            assert!(false);
+        },
+        _ = node2.run()  => {
+            assert!(false);
+        },
+        _ = node3.run()  => {
+            assert!(false);
+        },
+        _ = node4.run()  => {
+            assert!(false);
+        },
+        res = async {
+            let response = admin_client(&leader).await?.add_server(request).await?.into_inner();
+            sleep(Duration::from_secs(2)).await;
+            Ok::<_, gandalf_consensus::Error>(response)
+        } => {
+            let response = res?;
+            assert!(response.success);
+            assert_eq!(response.nodes.len(), 4);
+            assert!(response.learners.is_empty());
+        }
+    }
+
+    assert_eq!(node1.nodes.len(), 3);
+    assert_eq!(node4.state, State::Follower);
+
+    drop(node1);
+    drop(node2);
+    drop(node3);
+    drop(node4);
+
+    Ok(())
+}
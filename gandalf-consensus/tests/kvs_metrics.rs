@@ -0,0 +1,78 @@
+mod fixtures;
+
+use gandalf_consensus::metrics;
+use gandalf_consensus::raft::State;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::Duration;
+
+use fixtures::kvs_helpers::{client_write_requset, client_read_requset, kvs_cluster_of_nth};
+
+async fn http_get(addr: &str, path: &str) -> gandalf_consensus::Result<String> {
+    let mut stream = TcpStream::connect(addr).await?;
+    stream.write_all(format!("GET {} HTTP/1.0\r\n\r\n", path).as_bytes()).await?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await?;
+    Ok(response)
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 5)]
+async fn test_metrics_endpoint() -> gandalf_consensus::Result<()> {
+    let cluster = kvs_cluster_of_nth(3).await?;
+
+    let mut node1 = cluster.get(0).unwrap().0.borrow_mut();
+    let mut node2 = cluster.get(1).unwrap().0.borrow_mut();
+    let mut node3 = cluster.get(2).unwrap().0.borrow_mut();
+
+    node1.current_term = 1;
+    node1.set_state(State::Leader);
+
+    node2.current_term = 1;
+    node2.current_leader = Some(node1.id.clone());
+
+    node3.current_term = 1;
+    node3.current_leader = Some(node1.id.clone());
+
+    let metrics_addr = "127.0.0.1:7966";
+    tokio::spawn(metrics::serve(metrics_addr.parse()?, node1.metrics.clone()));
+
+    let connection_addr = format!("127.0.0.1:{}", 9876).to_string();
+
+    tokio::select! {
+        _ = node1.run() => {
+            assert!(false);
+        },
+        _ = node2.run()  => {
+            assert!(false);
+        },
+        _ = node3.run()  => {
+            assert!(false);
+        },
+        res = async {
+            client_write_requset(5, connection_addr.clone(), Duration::from_secs(0)).await?;
+            client_read_requset(5, connection_addr.clone(), Duration::from_secs(0)).await?;
+            http_get(metrics_addr, "/metrics").await
+        } => {
+            let body = res?;
+            assert!(body.starts_with("HTTP/1.0 200"));
+            assert!(body.contains("gandalf_current_term "));
+            assert!(body.contains("gandalf_last_applied_index 6\n"));
+            assert!(body.contains("gandalf_state{state=\"leader\"} 1\n"));
+            assert!(body.contains("gandalf_state{state=\"follower\"} 0\n"));
+            assert!(body.contains("gandalf_elections_won_total 1\n"));
+            assert!(body.contains("gandalf_client_request_duration_seconds_count{kind=\"write\"} 5\n"));
+            assert!(body.contains("gandalf_client_request_duration_seconds_count{kind=\"read\"} 5\n"));
+            assert!(body.contains("gandalf_follower_match_index{peer=\"127.0.0.1:7901\"}"));
+            assert!(body.contains("gandalf_follower_lag{peer=\"127.0.0.1:7902\"}"));
+        }
+    }
+
+    assert!(http_get(metrics_addr, "/").await?.starts_with("HTTP/1.0 404"));
+
+    drop(node1);
+    drop(node2);
+    drop(node3);
+
+    Ok(())
+}
@@ -0,0 +1,62 @@
+mod fixtures;
+
+use gandalf_consensus::Tracker;
+use gandalf_consensus::client::kvs::{KvsPool, KvsTracker};
+use gandalf_consensus::storage::FsyncPolicy;
+
+use gandalf_kvs::Frame;
+
+use tokio::time::{Duration, sleep};
+
+use fixtures::common::create_kvs_server;
+use fixtures::kvs_helpers::{set_frame, snapshot_dir};
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_pool_reuses_connections() -> gandalf_consensus::Result<()> {
+    let addr = create_kvs_server().await;
+    let pool = KvsPool::new(addr, 2);
+
+    for i in 0..10 {
+        pool.request(&set_frame("foo", i)).await?;
+    }
+    assert_eq!(pool.idle_connections(), 1);
+
+    // Never more connections than the pool size, however many requests wait.
+    let requests: Vec<_> = (0..8)
+        .map(|i| {
+            let pool = pool.clone();
+            tokio::spawn(async move { pool.request(&set_frame("foo", i)).await })
+        })
+        .collect();
+    for request in requests {
+        request.await??;
+    }
+    assert!(pool.idle_connections() <= 2);
+
+    // An idle connection gets probed before it is handed out again.
+    sleep(Duration::from_millis(1100)).await;
+    assert!(matches!(pool.request(&set_frame("foo", 0)).await?, Frame::Simple(_)));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_pool_shared_across_tracker_clones() -> gandalf_consensus::Result<()> {
+    let addr = create_kvs_server().await;
+    let tracker = KvsTracker::new(addr, snapshot_dir(7959), FsyncPolicy::Never)?;
+    let clone = tracker.clone();
+
+    tracker.propagate(&set_frame("foo", 1)).await?;
+    let get = Frame::Array(vec![
+        Frame::Simple("get".to_string()),
+        Frame::Simple("foo1".to_string())
+    ]);
+    assert!(matches!(clone.propagate(&get).await?, Frame::Bulk(_)));
+
+    // A command the store rejects keeps the connection usable.
+    let bogus = Frame::Array(vec![Frame::Simple("get".to_string())]);
+    assert!(tracker.propagate(&bogus).await.is_err());
+    assert!(matches!(clone.propagate(&get).await?, Frame::Bulk(_)));
+
+    Ok(())
+}
@@ -1,10 +1,12 @@
 mod fixtures;
 
 use gandalf_consensus::raft::State;
+use gandalf_consensus::Tracker;
+use gandalf_consensus::tracker::LogEntry;
 
 use tokio::time::{Duration, sleep};
 
-use fixtures::kvs_helpers::{client_write_requset, kvs_cluster_of_nth};
+use fixtures::kvs_helpers::{client_write_requset, kvs_cluster_of_nth, set_frame};
 
 #[tokio::test(flavor = "multi_thread", worker_threads = 5)]
 async fn test_log_replication() -> gandalf_consensus::Result<()> {
@@ -79,7 +81,7 @@ async fn test_log_replication() -> gandalf_consensus::Result<()> {
 
     let _: Vec<_> = cluster.
         iter()
-        .map(|c| assert!(c.0.borrow().get_commit_index() == 88)).collect();
+        .map(|c| assert!(c.0.borrow().get_commit_index() == 89)).collect();
 
     Ok(())
 }
@@ -154,7 +156,7 @@ async fn test_snapshot_replication() -> gandalf_consensus::Result<()> {
 
     let _: Vec<_> = cluster.
         iter()
-        .map(|c| assert!(c.0.borrow().get_commit_index() == 253)).collect();
+        .map(|c| assert!(c.0.borrow().get_commit_index() == 254)).collect();
 
     let _: Vec<_> = cluster.
         iter()
@@ -272,9 +274,307 @@ async fn test_failed_and_fixed_leader() -> gandalf_consensus::Result<()> {
         iter()
         .map(|c| assert!(c.0.borrow().current_term > 1)).collect();
 
+    // Every leader along the way commits a no-op next to the 150 writes.
+    let commit_index = cluster[0].0.borrow().get_commit_index();
+    assert!(commit_index >= 152);
+
     let _: Vec<_> = cluster.
         iter()
-        .map(|c| assert!(c.0.borrow().get_commit_index() == 150)).collect();
+        .map(|c| assert_eq!(c.0.borrow().get_commit_index(), commit_index)).collect();
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 5)]
+async fn test_conflicting_follower_log() -> gandalf_consensus::Result<()> {
+    let cluster = kvs_cluster_of_nth(3).await?;
+
+    let mut node1 = cluster.get(0).unwrap().0.borrow_mut();
+    let mut node2 = cluster.get(1).unwrap().0.borrow_mut();
+    let mut node3 = cluster.get(2).unwrap().0.borrow_mut();
+
+    // node2 holds entries from a stale term that never got commited
+    for i in 0..3 {
+        node2.tracker.write().await.append_log(LogEntry::Normal(set_frame("stale", i)), 1)?;
+    }
+    node2.update_last_log(3, 1);
+
+    node1.current_term = 2;
+    node1.set_state(State::Leader);
+
+    node2.current_term = 2;
+    node2.current_leader = Some(node1.id.clone());
+
+    node3.current_term = 2;
+    node3.current_leader = Some(node1.id.clone());
+
+    let connection_addr = format!("127.0.0.1:{}", 9876).to_string();
+
+    tokio::select! {
+        _ = node1.run() => {
+            assert!(false);
+        },
+        _ = node2.run()  => {
+            assert!(false);
+        },
+        _ = node3.run()  => {
+            assert!(false);
+        },
+        res = async {
+            client_write_requset(10, connection_addr, Duration::from_secs(0)).await?;
+            sleep(Duration::from_secs(2)).await;
+            Ok::<_, gandalf_consensus::Error>(())
+        } => {
+            res?
+        }
+    }
+
+    assert_eq!(node2.last_index(), node1.last_index());
+    assert_eq!(node2.last_term(), 2);
+    assert_eq!(node2.get_commit_index(), node1.get_commit_index());
+
+    drop(node1);
+    drop(node2);
+    drop(node3);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 5)]
+async fn test_batch_replication() -> gandalf_consensus::Result<()> {
+    let cluster = kvs_cluster_of_nth(3).await?;
+
+    let mut node1 = cluster.get(0).unwrap().0.borrow_mut();
+    let mut node2 = cluster.get(1).unwrap().0.borrow_mut();
+    let mut node3 = cluster.get(2).unwrap().0.borrow_mut();
+
+    node1.current_term = 1;
+    node1.set_state(State::Leader);
+    node1.max_batch_entries = 4;
+    node1.max_inflight = 2;
+
+    node2.current_term = 1;
+    node2.current_leader = Some(node1.id.clone());
+
+    node3.current_term = 1;
+    node3.current_leader = Some(node1.id.clone());
+
+    let connection_addr = format!("127.0.0.1:{}", 9876).to_string();
+
+    tokio::select! {
+        _ = node1.run() => {
+            assert!(false);
+        },
+        _ = node2.run()  => {
+            assert!(false);
+        },
+        res = client_write_requset(30, connection_addr, Duration::from_secs(0)) => {
+            res?
+        }
+    }
+
+    assert_eq!(node3.last_index(), 0);
+
+    tokio::select! {
+        _ = node1.run() => {
+            assert!(false);
+        },
+        _ = node2.run()  => {
+            assert!(false);
+        },
+        _ = node3.run()  => {
+            assert!(false);
+        },
+        _ = sleep(Duration::from_secs(3)) => {
+        }
+    }
+
+    assert_eq!(node3.last_index(), node1.last_index());
+    assert_eq!(node3.get_commit_index(), node1.get_commit_index());
+
+    drop(node1);
+    drop(node2);
+    drop(node3);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 5)]
+async fn test_conflict_hints() -> gandalf_consensus::Result<()> {
+    let cluster = kvs_cluster_of_nth(3).await?;
+
+    let mut node1 = cluster.get(0).unwrap().0.borrow_mut();
+    let mut node2 = cluster.get(1).unwrap().0.borrow_mut();
+    let mut node3 = cluster.get(2).unwrap().0.borrow_mut();
+
+    // All nodes share five entries from term 1, node2 then diverges with a
+    // long tail from term 2 while node1 and node3 moved on in term 3.
+    for node in [&mut node1, &mut node2, &mut node3] {
+        let mut tracker = node.tracker.write().await;
+        for i in 0..5 {
+            tracker.append_log(LogEntry::Normal(set_frame("common", i)), 1)?;
+        }
+    }
+    for i in 0..40 {
+        node2.tracker.write().await.append_log(LogEntry::Normal(set_frame("stale", i)), 2)?;
+    }
+    for node in [&mut node1, &mut node3] {
+        let mut tracker = node.tracker.write().await;
+        for i in 0..20 {
+            tracker.append_log(LogEntry::Normal(set_frame("fresh", i)), 3)?;
+        }
+    }
+    node1.update_last_log(25, 3);
+    node2.update_last_log(45, 2);
+    node3.update_last_log(25, 3);
+
+    node1.current_term = 3;
+    node1.set_state(State::Leader);
+
+    node2.current_term = 3;
+    node2.current_leader = Some(node1.id.clone());
+
+    node3.current_term = 3;
+    node3.current_leader = Some(node1.id.clone());
+
+    let connection_addr = format!("127.0.0.1:{}", 9876).to_string();
+
+    tokio::select! {
+        _ = node1.run() => {
+            assert!(false);
+        },
+        _ = node2.run()  => {
+            assert!(false);
+        },
+        _ = node3.run()  => {
+            assert!(false);
+        },
+        res = async {
+            client_write_requset(10, connection_addr, Duration::from_secs(0)).await?;
+            sleep(Duration::from_secs(2)).await;
+            Ok::<_, gandalf_consensus::Error>(())
+        } => {
+            res?
+        }
+    }
+
+    assert_eq!(node2.last_index(), node1.last_index());
+    assert_eq!(node2.last_term(), 3);
+    assert_eq!(node2.get_commit_index(), node1.get_commit_index());
+
+    drop(node1);
+    drop(node2);
+    drop(node3);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 5)]
+async fn test_chunked_snapshot_replication() -> gandalf_consensus::Result<()> {
+    let cluster = kvs_cluster_of_nth(3).await?;
+
+    let mut node1 = cluster.get(0).unwrap().0.borrow_mut();
+    let mut node2 = cluster.get(1).unwrap().0.borrow_mut();
+    let mut node3 = cluster.get(2).unwrap().0.borrow_mut();
+
+    node1.current_term = 1;
+    node1.set_state(State::Leader);
+    node1.snapshot_chunk_size = 128;
+
+    node2.current_term = 1;
+    node2.current_leader = Some(node1.id.clone());
+
+    node3.current_term = 1;
+    node3.current_leader = Some(node1.id.clone());
+
+    let connection_addr = format!("127.0.0.1:{}", 9876).to_string();
+
+    tokio::select! {
+        _ = node1.run() => {
+            assert!(false);
+        },
+        _ = node2.run()  => {
+            assert!(false);
+        },
+        res = client_write_requset(153, connection_addr, Duration::from_secs(0)) => {
+            res?
+        }
+    }
+
+    tokio::select! {
+        _ = node1.run() => {
+            assert!(false);
+        },
+        _ = node2.run()  => {
+            assert!(false);
+        },
+        _ = node3.run()  => {
+            assert!(false);
+        },
+        _ = sleep(Duration::from_secs(3)) => {
+        }
+    }
+
+    assert_eq!(node3.snapshot_num, 1);
+    assert_eq!(node3.get_commit_index(), node1.get_commit_index());
+    assert_eq!(node3.last_index(), node1.last_index());
+
+    drop(node1);
+    drop(node2);
+    drop(node3);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 5)]
+async fn test_noop_commits_previous_terms() -> gandalf_consensus::Result<()> {
+    let cluster = kvs_cluster_of_nth(3).await?;
+
+    let mut node1 = cluster.get(0).unwrap().0.borrow_mut();
+    let mut node2 = cluster.get(1).unwrap().0.borrow_mut();
+    let mut node3 = cluster.get(2).unwrap().0.borrow_mut();
+
+    for node in [&mut node1, &mut node2, &mut node3] {
+        let mut tracker = node.tracker.write().await;
+        for i in 0..5 {
+            tracker.append_log(LogEntry::Normal(set_frame("old", i)), 1)?;
+        }
+        drop(tracker);
+        node.update_last_log(5, 1);
+    }
+
+    node1.current_term = 2;
+    node1.set_state(State::Leader);
+
+    node2.current_term = 2;
+    node2.current_leader = Some(node1.id.clone());
+
+    node3.current_term = 2;
+    node3.current_leader = Some(node1.id.clone());
+
+    tokio::select! {
+        _ = node1.run() => {
+            assert!(false);
+        },
+        _ = node2.run()  => {
+            assert!(false);
+        },
+        _ = node3.run()  => {
+            assert!(false);
+        },
+        _ = sleep(Duration::from_secs(2)) => {
+        }
+    }
+
+    assert_eq!(node1.last_index(), 6);
+    assert_eq!(node1.last_term(), 2);
+    assert_eq!(node1.get_commit_index(), 6);
+    assert_eq!(node2.get_commit_index(), 6);
+    assert_eq!(node3.get_commit_index(), 6);
+
+    drop(node1);
+    drop(node2);
+    drop(node3);
 
     Ok(())
 }
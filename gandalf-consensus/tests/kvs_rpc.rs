@@ -0,0 +1,75 @@
+mod fixtures;
+
+use gandalf_consensus::Node;
+use gandalf_consensus::raft::State;
+use gandalf_consensus::rpc::ChannelPool;
+use gandalf_consensus::raft_rpc::RequestVoteRequest;
+
+use tokio::time::{Duration, Instant, sleep};
+
+use fixtures::kvs_helpers::kvs_cluster_of_nth;
+
+#[tokio::test]
+async fn test_unreachable_peer_backoff() -> gandalf_consensus::Result<()> {
+    let pool = ChannelPool::new(Duration::from_millis(500), None);
+    let node: Node = "127.0.0.1:7957".parse()?;
+    let request = RequestVoteRequest {
+        term: 1,
+        candidate_id: "127.0.0.1:7958".to_string(),
+        last_log_index: 0,
+        last_log_term: 0
+    };
+
+    assert!(pool.ask_for_vote(&node, request.clone()).await.is_err());
+    assert!(!pool.is_connected(&"127.0.0.1:7957".to_string()));
+
+    // The second call fails right away instead of dialing again.
+    let start = Instant::now();
+    let err = pool.ask_for_vote(&node, request).await.unwrap_err();
+    assert!(err.to_string().starts_with("Backing off"));
+    assert!(start.elapsed() < Duration::from_millis(50));
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 5)]
+async fn test_channels_reused_across_heartbeats() -> gandalf_consensus::Result<()> {
+    let cluster = kvs_cluster_of_nth(3).await?;
+
+    let mut node1 = cluster.get(0).unwrap().0.borrow_mut();
+    let mut node2 = cluster.get(1).unwrap().0.borrow_mut();
+    let mut node3 = cluster.get(2).unwrap().0.borrow_mut();
+
+    node1.current_term = 1;
+    node1.set_state(State::Leader);
+
+    node2.current_term = 1;
+    node2.current_leader = Some(node1.id.clone());
+
+    node3.current_term = 1;
+    node3.current_leader = Some(node1.id.clone());
+
+    tokio::select! {
+        _ = node1.run() => {
+            assert!(false);
+        },
+        _ = node2.run()  => {
+            assert!(false);
+        },
+        _ = node3.run()  => {
+            assert!(false);
+        },
+        _ = sleep(Duration::from_secs(2)) => {
+        }
+    }
+
+    assert!(node1.peers.is_connected(&node2.id));
+    assert!(node1.peers.is_connected(&node3.id));
+    assert_eq!(node1.state, State::Leader);
+
+    drop(node1);
+    drop(node2);
+    drop(node3);
+
+    Ok(())
+}
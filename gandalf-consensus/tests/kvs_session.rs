@@ -0,0 +1,123 @@
+mod fixtures;
+
+use gandalf_consensus::Tracker;
+use gandalf_consensus::tracker::LogEntry;
+use gandalf_consensus::parser::{Kind, Parser, Session};
+use gandalf_consensus::client::kvs::{KvsParser, KvsTracker};
+use gandalf_consensus::storage::FsyncPolicy;
+
+use gandalf_kvs::{client, Frame};
+
+use bytes::BytesMut;
+
+use fixtures::common::create_kvs_server;
+use fixtures::kvs_helpers::snapshot_dir;
+
+fn write(key: &str, value: &str) -> LogEntry<Frame> {
+    LogEntry::Normal(Frame::Array(vec![
+        Frame::Simple("set".to_string()),
+        Frame::Simple(key.to_string()),
+        Frame::Bulk(value.to_string().into())
+    ]))
+}
+
+fn session_write(key: &str, value: &str, sequence: u64) -> LogEntry<Frame> {
+    LogEntry::Session {
+        session: Session { client_id: "client1".to_string(), sequence },
+        entity: Frame::Array(vec![
+            Frame::Simple("set".to_string()),
+            Frame::Simple(key.to_string()),
+            Frame::Bulk(value.to_string().into())
+        ])
+    }
+}
+
+#[test]
+fn test_parse_session_prefix() -> gandalf_consensus::Result<()> {
+    let parser = KvsParser;
+    let frame = Frame::Array(vec![
+        Frame::Bulk("SESSION".into()),
+        Frame::Bulk("client1".into()),
+        Frame::Bulk("7".into()),
+        Frame::Bulk("set".into()),
+        Frame::Bulk("foo".into()),
+        Frame::Bulk("bar".into())
+    ]);
+    let mut buffer = BytesMut::from(&parser.unparse(frame)?[..]);
+
+    match parser.parse(&mut buffer)? {
+        Some(Kind::Write(Frame::Array(parts), Some(session))) => {
+            assert_eq!(parts.len(), 3);
+            assert_eq!(session, Session { client_id: "client1".to_string(), sequence: 7 });
+        },
+        _ => panic!("expected a write with a session"),
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_duplicate_session_writes() -> gandalf_consensus::Result<()> {
+    let path = snapshot_dir(7956);
+    let addr = create_kvs_server().await;
+    let mut tracker = KvsTracker::new(addr, path.clone(), FsyncPolicy::Never)?;
+
+    tracker.append_log(session_write("foo", "1", 1), 1)?;
+    tracker.append_log(session_write("foo", "2", 2), 1)?;
+    // A retry of the first write after the second one already applied.
+    tracker.append_log(session_write("foo", "1", 1), 1)?;
+    // A retry of the latest write gets the cached response.
+    tracker.append_log(session_write("foo", "3", 2), 1)?;
+
+    assert!(matches!(tracker.commit(0).await?, LogEntry::Normal(Frame::Simple(_))));
+    tracker.commit(1).await?;
+    assert!(matches!(tracker.commit(2).await?, LogEntry::Normal(Frame::Error(_))));
+    assert!(matches!(tracker.commit(3).await?, LogEntry::Normal(Frame::Simple(_))));
+
+    let mut con = client::connect(addr).await?;
+    assert_eq!(con.get("foo").await?, Some("2".into()));
+
+    tracker.take_snapshot().await?;
+    tracker.append_log(session_write("foo", "4", 2), 1)?;
+    drop(tracker);
+
+    let addr = create_kvs_server().await;
+    let mut tracker = KvsTracker::new(addr, path, FsyncPolicy::Never)?;
+    tracker.recover().await?;
+    tracker.commit(4).await?;
+
+    let mut con = client::connect(addr).await?;
+    assert_eq!(con.get("foo").await?, Some("2".into()));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_idle_session_expires() -> gandalf_consensus::Result<()> {
+    let addr = create_kvs_server().await;
+    let mut tracker = KvsTracker::new(addr, snapshot_dir(7972), FsyncPolicy::Never)?;
+    tracker.set_session_expiry(4);
+
+    tracker.append_log(session_write("foo", "1", 1), 1)?;
+    tracker.append_log(write("foo", "2"), 1)?;
+    // Still cached, so the retry does not apply again.
+    tracker.append_log(session_write("foo", "3", 1), 1)?;
+    for i in 4..=8 {
+        tracker.append_log(write("bar", &i.to_string()), 1)?;
+    }
+    // Swept at index 8, the same sequence is a new write now.
+    tracker.append_log(session_write("foo", "9", 1), 1)?;
+
+    let mut con = client::connect(addr).await?;
+    for i in 0..3 {
+        tracker.commit(i).await?;
+    }
+    assert_eq!(con.get("foo").await?, Some("2".into()));
+
+    for i in 3..9 {
+        tracker.commit(i).await?;
+    }
+    assert_eq!(con.get("foo").await?, Some("9".into()));
+
+    Ok(())
+}
@@ -1,10 +1,17 @@
 mod fixtures;
 
+use gandalf_consensus::Tracker;
+use gandalf_consensus::tracker::LogEntry;
 use gandalf_consensus::raft::State;
+use gandalf_consensus::client::kvs::KvsTracker;
+use gandalf_consensus::storage::FsyncPolicy;
+
+use gandalf_kvs::{client, Frame};
 
 use tokio::time::Duration;
 
-use fixtures::kvs_helpers::{client_write_requset, kvs_cluster_of_nth};
+use fixtures::common::create_kvs_server;
+use fixtures::kvs_helpers::{client_write_requset, kvs_cluster_of_nth, snapshot_dir};
 
 #[tokio::test(flavor = "multi_thread", worker_threads = 5)]
 async fn test_snapshot() -> gandalf_consensus::Result<()> {
@@ -66,3 +73,159 @@ async fn test_snapshot() -> gandalf_consensus::Result<()> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn test_recover_from_snapshot() -> gandalf_consensus::Result<()> {
+    let path = snapshot_dir(7952);
+
+    let addr = create_kvs_server().await;
+    let mut tracker = KvsTracker::new(addr, path.clone(), FsyncPolicy::Never)?;
+    for i in 1..=150 {
+        let frame = Frame::Array(vec![
+            Frame::Simple("set".to_string()),
+            Frame::Simple(format!("foo{}", i)),
+            Frame::Bulk(format!("{}", i).into())
+        ]);
+        tracker.append_log(LogEntry::Normal(frame), 1)?;
+    }
+    for i in 0..120 {
+        tracker.commit(i).await?;
+    }
+    tracker.take_snapshot().await?;
+    for i in 120..140 {
+        tracker.commit(i).await?;
+    }
+    tracker.save_commit_index()?;
+    drop(tracker);
+
+    let addr = create_kvs_server().await;
+    let mut tracker = KvsTracker::new(addr, path, FsyncPolicy::Never)?;
+    tracker.recover().await?;
+
+    assert_eq!(tracker.get_snapshot_no(), 1);
+    assert_eq!(tracker.get_last_snapshot_index(), 120);
+    assert_eq!(tracker.get_last_snapshot_term(), 1);
+    assert_eq!(tracker.get_last_commited_index(), 140);
+    assert_eq!(tracker.get_last_log_index(), 150);
+
+    let mut con = client::connect(addr).await?;
+    assert!(con.get("foo100").await?.is_some());
+    assert!(con.get("foo140").await?.is_some());
+    assert!(con.get("foo141").await?.is_none());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_chunked_snapshot_transfer() -> gandalf_consensus::Result<()> {
+    let addr = create_kvs_server().await;
+    let mut leader = KvsTracker::new(addr, snapshot_dir(7953), FsyncPolicy::Never)?;
+    for i in 1..=100 {
+        let frame = Frame::Array(vec![
+            Frame::Simple("set".to_string()),
+            Frame::Simple(format!("foo{}", i)),
+            Frame::Bulk(format!("{}", i).into())
+        ]);
+        leader.append_log(LogEntry::Normal(frame), 1)?;
+    }
+    for i in 0..100 {
+        leader.commit(i).await?;
+    }
+    leader.take_snapshot().await?;
+
+    let addr = create_kvs_server().await;
+    let mut follower = KvsTracker::new(addr, snapshot_dir(7955), FsyncPolicy::Never)?;
+
+    let mut offset = 0;
+    let mut chunks = 0;
+    loop {
+        let (data, done) = leader.read_snapshot(offset, 64).await?;
+        chunks += 1;
+        // The second chunk gets lost, the follower asks to resume from its end.
+        if chunks == 2 {
+            let (lost, _) = leader.read_snapshot(offset + data.len() as u64, 64).await?;
+            let resume = follower.stage_snapshot(1, 100, offset + data.len() as u64, &lost)?;
+            assert_eq!(resume, offset);
+        }
+        offset = follower.stage_snapshot(1, 100, offset, &data)?;
+        if done {
+            break;
+        }
+    }
+    assert!(chunks > 2);
+    follower.load_snapshot(1, 100, leader.get_snapshot_no()).await?;
+
+    assert_eq!(follower.get_snapshot_no(), 1);
+    assert_eq!(follower.get_last_snapshot_index(), 100);
+    assert_eq!(follower.get_last_commited_index(), 100);
+
+    let mut con = client::connect(addr).await?;
+    assert!(con.get("foo1").await?.is_some());
+    assert!(con.get("foo100").await?.is_some());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_recover_from_installed_snapshot() -> gandalf_consensus::Result<()> {
+    let addr = create_kvs_server().await;
+    let mut leader = KvsTracker::new(addr, snapshot_dir(7970), FsyncPolicy::Never)?;
+    for i in 1..=100 {
+        let frame = Frame::Array(vec![
+            Frame::Simple("set".to_string()),
+            Frame::Simple(format!("foo{}", i)),
+            Frame::Bulk(format!("{}", i).into())
+        ]);
+        leader.append_log(LogEntry::Normal(frame), 1)?;
+    }
+    for i in 0..100 {
+        leader.commit(i).await?;
+    }
+    leader.take_snapshot().await?;
+
+    // The follower numbered its own snapshots past the leader's.
+    let path = snapshot_dir(7971);
+    let addr = create_kvs_server().await;
+    let mut follower = KvsTracker::new(addr, path.clone(), FsyncPolicy::Never)?;
+    for i in 1..=30 {
+        let frame = Frame::Array(vec![
+            Frame::Simple("set".to_string()),
+            Frame::Simple(format!("foo{}", i)),
+            Frame::Bulk(format!("{}", i).into())
+        ]);
+        follower.append_log(LogEntry::Normal(frame), 1)?;
+        follower.commit(i - 1).await?;
+        if i % 10 == 0 {
+            follower.take_snapshot().await?;
+        }
+    }
+    assert_eq!(follower.get_snapshot_no(), 3);
+
+    let (data, _) = leader.read_snapshot(0, u64::MAX).await?;
+    follower.stage_snapshot(1, 100, 0, &data)?;
+    follower.load_snapshot(1, 100, leader.get_snapshot_no()).await?;
+    drop(follower);
+
+    let addr = create_kvs_server().await;
+    let mut follower = KvsTracker::new(addr, path, FsyncPolicy::Never)?;
+    follower.recover().await?;
+
+    assert_eq!(follower.get_last_snapshot_index(), 100);
+    assert_eq!(follower.get_last_commited_index(), 100);
+    assert_eq!(follower.get_last_log_index(), 100);
+
+    let mut con = client::connect(addr).await?;
+    assert!(con.get("foo100").await?.is_some());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_read_snapshot_before_any_taken() -> gandalf_consensus::Result<()> {
+    let addr = create_kvs_server().await;
+    let tracker = KvsTracker::new(addr, snapshot_dir(7973), FsyncPolicy::Never)?;
+    assert_eq!(tracker.get_snapshot_no(), 0);
+    assert!(tracker.read_snapshot(0, 64).await.is_err());
+
+    Ok(())
+}
@@ -0,0 +1,134 @@
+mod fixtures;
+
+use gandalf_consensus::{Node, RaftMessage};
+use gandalf_consensus::server::serve_rpc;
+use gandalf_consensus::rpc::ChannelPool;
+use gandalf_consensus::tls::PeerTls;
+use gandalf_consensus::raft_rpc::{RequestVoteRequest, RequestVoteResponse};
+
+use gandalf_kvs::Frame;
+
+use rcgen::{BasicConstraints, Certificate, CertificateParams, ExtendedKeyUsagePurpose, IsCa, KeyPair};
+
+use tokio::sync::mpsc;
+use tokio::time::{Duration, sleep};
+
+use std::collections::HashSet;
+
+use fixtures::kvs_helpers::snapshot_dir;
+
+struct Authority {
+    cert: Certificate,
+    key: KeyPair
+}
+
+fn authority() -> gandalf_consensus::Result<Authority> {
+    let key = KeyPair::generate()?;
+    let mut params = CertificateParams::new(Vec::new())?;
+    params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    let cert = params.self_signed(&key)?;
+    Ok(Authority { cert, key })
+}
+
+// Writes <name>.ca, <name>.crt and <name>.key under dir.
+fn issue(dir: &str, name: &str, ca: &Authority, ip: &str) -> gandalf_consensus::Result<PeerTls> {
+    let key = KeyPair::generate()?;
+    let mut params = CertificateParams::new(vec![ip.to_string()])?;
+    params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth,
+        ExtendedKeyUsagePurpose::ClientAuth];
+    let cert = params.signed_by(&key, &ca.cert, &ca.key)?;
+
+    std::fs::create_dir_all(dir)?;
+    let path = |ext: &str| format!("{}/{}.{}", dir, name, ext);
+    std::fs::write(path("ca"), ca.cert.pem())?;
+    std::fs::write(path("crt"), cert.pem())?;
+    std::fs::write(path("key"), key.serialize_pem())?;
+    PeerTls::load(path("ca"), path("crt"), path("key"))
+}
+
+fn start_server(port: u16, tls: PeerTls) {
+    let (tx_rpc, mut rx_rpc) = mpsc::unbounded_channel::<RaftMessage<Frame>>();
+    tokio::spawn(async move {
+        while let Some(msg) = rx_rpc.recv().await {
+            if let RaftMessage::VoteMsg{tx, ..} = msg {
+                let _ = tx.send(RaftMessage::VoteResp {
+                    payload: RequestVoteResponse { term: 1, vote_granted: true },
+                    status: None
+                });
+            }
+        }
+    });
+    let addr = format!("127.0.0.1:{}", port).parse().unwrap();
+    tokio::spawn(serve_rpc(tx_rpc, addr, Some(tls)));
+}
+
+async fn ask(port: u16, tls: &PeerTls) -> gandalf_consensus::Result<RequestVoteResponse> {
+    let pool = ChannelPool::new(Duration::from_millis(500), Some(tls.clone()));
+    let node: Node = format!("127.0.0.1:{}", port).parse()?;
+    pool.ask_for_vote(&node, RequestVoteRequest {
+        term: 1,
+        candidate_id: "127.0.0.1:7999".to_string(),
+        last_log_index: 0,
+        last_log_term: 0
+    }).await
+}
+
+fn members(ips: &[&str]) -> HashSet<std::net::IpAddr> {
+    ips.iter().map(|ip| ip.parse().unwrap()).collect()
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_peer_identity_checked() -> gandalf_consensus::Result<()> {
+    let dir = snapshot_dir(7960);
+    let ca = authority()?;
+    let other_ca = authority()?;
+
+    let server = issue(&dir, "server", &ca, "127.0.0.1")?;
+    server.set_members(members(&["127.0.0.1"]));
+    start_server(7960, server);
+
+    let member = issue(&dir, "member", &ca, "127.0.0.1")?;
+    let stranger = issue(&dir, "stranger", &ca, "127.0.0.2")?;
+    let foreign = issue(&dir, "foreign", &other_ca, "127.0.0.1")?;
+
+    sleep(Duration::from_millis(200)).await;
+
+    assert!(ask(7960, &member).await?.vote_granted);
+    // Signed by the right CA, but not for a member ip.
+    assert!(ask(7960, &stranger).await.is_err());
+    assert!(ask(7960, &foreign).await.is_err());
+
+    // The server's certificate must name the ip that was dialed.
+    let wrong_ip = issue(&dir, "wrong_ip", &ca, "127.0.0.3")?;
+    wrong_ip.set_members(members(&["127.0.0.1"]));
+    start_server(7961, wrong_ip);
+    sleep(Duration::from_millis(200)).await;
+    assert!(ask(7961, &member).await.is_err());
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_peer_certificates_reload() -> gandalf_consensus::Result<()> {
+    let dir = snapshot_dir(7962);
+    let old_ca = authority()?;
+    let new_ca = authority()?;
+
+    let server = issue(&dir, "server", &old_ca, "127.0.0.1")?;
+    server.set_members(members(&["127.0.0.1"]));
+    start_server(7962, server.clone());
+
+    let client = issue(&dir, "client", &new_ca, "127.0.0.1")?;
+
+    sleep(Duration::from_millis(200)).await;
+    assert!(ask(7962, &client).await.is_err());
+    assert!(!server.reload()?);
+
+    // Rotate the server onto the new CA by rewriting its files in place.
+    issue(&dir, "server", &new_ca, "127.0.0.1")?;
+    assert!(server.reload()?);
+
+    assert!(ask(7962, &client).await?.vote_granted);
+
+    Ok(())
+}
@@ -0,0 +1,72 @@
+mod fixtures;
+
+use gandalf_consensus::Tracker;
+use gandalf_consensus::tracker::LogEntry;
+use gandalf_consensus::client::kvs::KvsTracker;
+use gandalf_consensus::storage::FsyncPolicy;
+
+use gandalf_kvs::Frame;
+
+use fixtures::kvs_helpers::{set_frame, snapshot_dir};
+
+#[test]
+fn test_log_survives_restart() -> gandalf_consensus::Result<()> {
+    let path = snapshot_dir(7950);
+    let addr = "127.0.0.1:9736".parse()?;
+
+    let mut tracker = KvsTracker::new(addr, path.clone(), FsyncPolicy::Always)?;
+    for i in 1..=20 {
+        tracker.append_log(LogEntry::Normal(set_frame("foo", i)), 1 + i / 10)?;
+    }
+    tracker.delete_last_log()?;
+    drop(tracker);
+
+    let tracker = KvsTracker::new(addr, path, FsyncPolicy::Always)?;
+
+    assert_eq!(tracker.get_last_log_index(), 19);
+    assert_eq!(tracker.get_last_log_term(), 2);
+    assert_eq!(tracker.get_log_term(9), 1);
+    assert_eq!(tracker.get_log_term(10), 2);
+    match tracker.get_log_entity(7) {
+        LogEntry::Normal(Frame::Array(frames)) => assert!(matches!(&frames[1], Frame::Simple(key) if key == "foo7")),
+        _ => panic!("unexpected log entity")
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_corrupted_tail_is_truncated() -> gandalf_consensus::Result<()> {
+    let path = snapshot_dir(7974);
+    let addr = "127.0.0.1:9736".parse()?;
+
+    let mut tracker = KvsTracker::new(addr, path.clone(), FsyncPolicy::Always)?;
+    for i in 1..=5 {
+        tracker.append_log(LogEntry::Normal(set_frame("foo", i)), 1)?;
+    }
+    drop(tracker);
+
+    // Flip the term of the last record, it still parses but fails its checksum.
+    let segment = format!("{}/wal/{:020}.wal", path, 1);
+    let mut data = std::fs::read(&segment)?;
+    let (mut offset, mut last) = (0, 0);
+    while offset < data.len() {
+        last = offset;
+        let mut len = [0; 4];
+        len.copy_from_slice(&data[offset..offset + 4]);
+        offset += 24 + u32::from_be_bytes(len) as usize;
+    }
+    data[last + 23] ^= 0xff;
+    std::fs::write(&segment, data)?;
+
+    let mut tracker = KvsTracker::new(addr, path.clone(), FsyncPolicy::Always)?;
+    assert_eq!(tracker.get_last_log_index(), 4);
+    tracker.append_log(LogEntry::Normal(set_frame("bar", 5)), 2)?;
+    drop(tracker);
+
+    let tracker = KvsTracker::new(addr, path, FsyncPolicy::Always)?;
+    assert_eq!(tracker.get_last_log_index(), 5);
+    assert_eq!(tracker.get_log_term(5), 2);
+
+    Ok(())
+}